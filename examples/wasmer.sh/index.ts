@@ -36,6 +36,10 @@ async function main() {
     term.reset();
     const instance = await pkg.entrypoint!.run({ args, uses });
     connectStreams(instance, term);
+
+    instance.resize(term.cols, term.rows);
+    term.onResize(({ cols, rows }) => instance.resize(cols, rows));
+    window.addEventListener("resize", () => fit.fit());
 }
 
 function connectStreams(instance: Instance, term: Terminal) {
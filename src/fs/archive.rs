@@ -0,0 +1,472 @@
+//! Importing and exporting the contents of a [`FileSystem`] as archives.
+
+use std::{
+    io::{Cursor, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use futures::future::LocalBoxFuture;
+use virtual_fs::{AsyncReadExt, AsyncWriteExt, FileSystem};
+
+use crate::{
+    fs::{
+        attributes::{AttributeFileSystem, Attributes},
+        Directory,
+    },
+    utils::Error,
+};
+
+pub(crate) use zip::CompressionMethod;
+
+/// The first two bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// The mode used for files whose permissions aren't known.
+pub(crate) const DEFAULT_FILE_MODE: u32 = 0o644;
+/// The mode used for directories whose permissions aren't known.
+pub(crate) const DEFAULT_DIR_MODE: u32 = 0o755;
+
+/// An entry found while walking a [`FileSystem`].
+#[derive(Debug)]
+pub(crate) struct Entry {
+    /// The entry's path, relative to the directory being walked.
+    pub path: PathBuf,
+    pub metadata: virtual_fs::Metadata,
+    /// The entry's permission bits, if they are known.
+    pub mode: Option<u32>,
+    /// The file's contents, or `None` if this is a directory.
+    pub contents: Option<Vec<u8>>,
+}
+
+/// Recursively read everything under `root`, with parents always coming
+/// before their children.
+pub(crate) async fn walk(fs: &Directory, root: &Path) -> Result<Vec<Entry>, Error> {
+    let mut entries = Vec::new();
+    walk_dir(fs, root, Path::new(""), &mut entries).await?;
+    Ok(entries)
+}
+
+fn walk_dir<'a>(
+    fs: &'a Directory,
+    dir: &'a Path,
+    relative: &'a Path,
+    entries: &'a mut Vec<Entry>,
+) -> LocalBoxFuture<'a, Result<(), Error>> {
+    Box::pin(async move {
+        let mut children = FileSystem::read_dir(fs, dir)
+            .with_context(|| format!("Unable to read \"{}\"", dir.display()))?
+            .collect::<Result<Vec<_>, _>>()?;
+        // Make sure archives are reproducible
+        children.sort_by(|a, b| a.path.cmp(&b.path));
+
+        for child in children {
+            let relative = relative.join(child.file_name());
+            let metadata = FileSystem::metadata(fs, &child.path)?;
+            let mode = fs.mode(&child.path);
+
+            if metadata.is_dir() {
+                entries.push(Entry {
+                    path: relative.clone(),
+                    metadata,
+                    mode,
+                    contents: None,
+                });
+                walk_dir(fs, &child.path, &relative, entries).await?;
+            } else if metadata.is_file() {
+                let contents = read_file(fs, &child.path).await?;
+                entries.push(Entry {
+                    path: relative,
+                    metadata,
+                    mode,
+                    contents: Some(contents),
+                });
+            } else {
+                tracing::debug!(
+                    path = %child.path.display(),
+                    "Skipping an entry which isn't a file or directory",
+                );
+            }
+        }
+
+        Ok(())
+    })
+}
+
+async fn read_file(fs: &dyn FileSystem, path: &Path) -> Result<Vec<u8>, Error> {
+    let mut f = fs.new_open_options().read(true).open(path)?;
+    let mut buffer = Vec::with_capacity(f.size() as usize);
+    f.read_to_end(&mut buffer)
+        .await
+        .with_context(|| format!("Unable to read \"{}\"", path.display()))?;
+
+    Ok(buffer)
+}
+
+/// Write a file to the [`FileSystem`], creating any parent directories that
+/// don't exist yet.
+pub(crate) async fn write_file(
+    fs: &dyn FileSystem,
+    path: &Path,
+    contents: &[u8],
+) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        super::directory::create_dir_all(fs, parent)?;
+    }
+
+    let mut f = fs
+        .new_open_options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    f.write_all(contents)
+        .await
+        .with_context(|| format!("Unable to write to \"{}\"", path.display()))?;
+    f.flush().await?;
+
+    Ok(())
+}
+
+/// Turn a path from an archive into an absolute path, rejecting anything that
+/// would escape the root directory.
+pub(crate) fn sanitize_path(path: &Path) -> Result<PathBuf, Error> {
+    let mut sanitized = PathBuf::from("/");
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => sanitized.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(anyhow::anyhow!(
+                    "Refusing to extract \"{}\" because it is outside the archive's root",
+                    path.display()
+                )
+                .into());
+            }
+        }
+    }
+
+    Ok(sanitized)
+}
+
+/// Unpack a (possibly gzipped) tarball into a new in-memory [`FileSystem`],
+/// preserving each entry's mode and modification time.
+pub(crate) async fn from_tar(bytes: &[u8]) -> Result<AttributeFileSystem, Error> {
+    let fs = AttributeFileSystem::new(Arc::new(virtual_fs::mem_fs::FileSystem::default()));
+
+    let reader: Box<dyn Read + '_> = if bytes.starts_with(&GZIP_MAGIC) {
+        Box::new(flate2::read::GzDecoder::new(bytes))
+    } else {
+        Box::new(bytes)
+    };
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries().context("Unable to read the tarball")? {
+        let mut entry = entry.context("Invalid tarball entry")?;
+        let path = sanitize_path(&entry.path()?)?;
+        let header = entry.header();
+        // Note: tar stores timestamps in seconds, but we use nanoseconds
+        let modified = header
+            .mtime()?
+            .checked_mul(1_000_000_000)
+            .with_context(|| {
+                format!(
+                    "The modification time for \"{}\" is out of range",
+                    path.display()
+                )
+            })?;
+        let attributes = Attributes {
+            mode: header.mode()? & 0o7777,
+            modified: Some(modified),
+        };
+
+        match header.entry_type() {
+            tar::EntryType::Directory => {
+                super::directory::create_dir_all(&fs, &path)?;
+                fs.set_attributes(&path, attributes);
+            }
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let mut contents = Vec::with_capacity(entry.size() as usize);
+                entry
+                    .read_to_end(&mut contents)
+                    .with_context(|| format!("Unable to read \"{}\"", path.display()))?;
+
+                tracing::trace!(
+                    path=%path.display(),
+                    file.length=contents.len(),
+                    "Extracting file from tarball",
+                );
+                write_file(&fs, &path, &contents).await?;
+                fs.set_attributes(&path, attributes);
+            }
+            other => {
+                tracing::debug!(
+                    path=%path.display(),
+                    entry_type=?other,
+                    "Skipping unsupported tarball entry",
+                );
+            }
+        }
+    }
+
+    Ok(fs)
+}
+
+/// Pack everything under `root` into a tarball.
+pub(crate) async fn to_tar(fs: &Directory, root: &Path, gzip: bool) -> Result<Vec<u8>, Error> {
+    let entries = walk(fs, root).await?;
+
+    if gzip {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let encoder = write_tar(encoder, &entries)?;
+        Ok(encoder.finish()?)
+    } else {
+        write_tar(Vec::new(), &entries)
+    }
+}
+
+fn write_tar<W: Write>(writer: W, entries: &[Entry]) -> Result<W, Error> {
+    let mut builder = tar::Builder::new(writer);
+
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        // Note: timestamps are stored in nanoseconds, but tar uses seconds
+        header.set_mtime(entry.metadata.modified / 1_000_000_000);
+
+        match &entry.contents {
+            Some(contents) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(entry.mode.unwrap_or(DEFAULT_FILE_MODE));
+                header.set_size(contents.len() as u64);
+                builder.append_data(&mut header, &entry.path, contents.as_slice())?;
+            }
+            None => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(entry.mode.unwrap_or(DEFAULT_DIR_MODE));
+                header.set_size(0);
+                builder.append_data(&mut header, &entry.path, std::io::empty())?;
+            }
+        }
+    }
+
+    let writer = builder.into_inner()?;
+    Ok(writer)
+}
+
+/// Unpack a zip archive into a new in-memory [`FileSystem`].
+///
+/// Both stored and deflated entries are supported. As with tarballs, each
+/// entry's mode and modification time are preserved.
+pub(crate) async fn from_zip(bytes: &[u8]) -> Result<AttributeFileSystem, Error> {
+    let fs = AttributeFileSystem::new(Arc::new(virtual_fs::mem_fs::FileSystem::default()));
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).context("Invalid zip archive")?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+
+        // Note: This takes care of decoding non-UTF-8 (i.e. CP437) names and
+        // rejecting anything that would escape the archive's root.
+        let path = match entry.enclosed_name() {
+            Some(name) => sanitize_path(name)?,
+            None => {
+                return Err(anyhow::anyhow!(
+                    "Refusing to extract \"{}\" because it is outside the archive's root",
+                    entry.name()
+                )
+                .into());
+            }
+        };
+
+        let default_mode = if entry.is_dir() {
+            DEFAULT_DIR_MODE
+        } else {
+            DEFAULT_FILE_MODE
+        };
+        let attributes = Attributes {
+            // Note: archives created on Windows won't have a Unix mode
+            mode: entry.unix_mode().map_or(default_mode, |mode| mode & 0o7777),
+            modified: Some(unix_timestamp(entry.last_modified())),
+        };
+
+        if entry.is_dir() {
+            super::directory::create_dir_all(&fs, &path)?;
+            fs.set_attributes(&path, attributes);
+            continue;
+        }
+
+        let mut contents = Vec::with_capacity(entry.size() as usize);
+        entry
+            .read_to_end(&mut contents)
+            .with_context(|| format!("Unable to read \"{}\"", path.display()))?;
+
+        tracing::trace!(
+            path=%path.display(),
+            file.length=contents.len(),
+            compression=?entry.compression(),
+            "Extracting file from zip archive",
+        );
+        write_file(&fs, &path, &contents).await?;
+        fs.set_attributes(&path, attributes);
+    }
+
+    Ok(fs)
+}
+
+/// Pack everything under `root` into a zip archive.
+pub(crate) async fn to_zip(
+    fs: &Directory,
+    root: &Path,
+    compression: CompressionMethod,
+) -> Result<Vec<u8>, Error> {
+    let entries = walk(fs, root).await?;
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+    for entry in &entries {
+        let name = entry.path.to_string_lossy();
+        let options = zip::write::FileOptions::default()
+            .compression_method(compression)
+            .last_modified_time(zip_timestamp(entry.metadata.modified));
+
+        match &entry.contents {
+            Some(contents) => {
+                let mode = entry.mode.unwrap_or(DEFAULT_FILE_MODE);
+                writer.start_file(name, options.unix_permissions(mode))?;
+                writer.write_all(contents)?;
+            }
+            None => {
+                let mode = entry.mode.unwrap_or(DEFAULT_DIR_MODE);
+                writer.add_directory(name, options.unix_permissions(mode))?;
+            }
+        }
+    }
+
+    let cursor = writer.finish()?;
+    Ok(cursor.into_inner())
+}
+
+/// Convert a timestamp in nanoseconds since the Unix epoch to a
+/// [`zip::DateTime`], falling back to the earliest date zip supports
+/// (1980-01-01) if it is out of range.
+fn zip_timestamp(nanos: u64) -> zip::DateTime {
+    let secs = nanos / 1_000_000_000;
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+
+    u16::try_from(year)
+        .ok()
+        .and_then(|year| {
+            zip::DateTime::from_date_and_time(
+                year,
+                month,
+                day,
+                (secs_of_day / 3600) as u8,
+                (secs_of_day % 3600 / 60) as u8,
+                (secs_of_day % 60) as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Convert a [`zip::DateTime`] to a timestamp in nanoseconds since the Unix
+/// epoch.
+///
+/// Zip timestamps don't have a timezone, so they are treated as UTC. Dates
+/// before the Unix epoch are clamped to it.
+fn unix_timestamp(date: zip::DateTime) -> u64 {
+    let days = days_from_civil(date.year().into(), date.month(), date.day());
+    let secs = days * 86_400
+        + i64::from(date.hour()) * 3600
+        + i64::from(date.minute()) * 60
+        + i64::from(date.second());
+
+    // Note: zip can't store dates past 2107, so this should never saturate
+    u64::try_from(secs)
+        .unwrap_or(0)
+        .saturating_mul(1_000_000_000)
+}
+
+/// Convert a number of days since the Unix epoch to a `(year, month, day)`
+/// date in the proleptic Gregorian calendar.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// The inverse of [`civil_from_days()`].
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn convert_days_since_epoch_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+
+    #[wasm_bindgen_test]
+    fn convert_dates_to_days_since_epoch() {
+        for days in [0, 59, 3_652, 19_723, 19_782] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn the_latest_zip_timestamp_fits_in_nanoseconds() {
+        let latest = zip::DateTime::from_date_and_time(2107, 12, 31, 23, 59, 59).unwrap();
+
+        let nanos = unix_timestamp(latest);
+
+        assert_eq!(nanos, 4_354_819_199 * 1_000_000_000);
+    }
+
+    #[wasm_bindgen_test]
+    async fn reject_tar_timestamps_that_overflow() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o644);
+        header.set_mtime(u64::MAX);
+        header.set_entry_type(tar::EntryType::Regular);
+        builder
+            .append_data(&mut header, "file.txt", std::io::empty())
+            .unwrap();
+        let tarball = builder.into_inner().unwrap();
+
+        let err = from_tar(&tarball).await.unwrap_err().into_anyhow();
+
+        assert!(err.to_string().contains("out of range"), "{err:?}");
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use futures::future::BoxFuture;
+use virtual_fs::{FileSystem, VirtualFile};
+
+/// A [`FileSystem`] wrapper which remembers each entry's Unix permissions
+/// and modification time.
+///
+/// The in-memory filesystem has nowhere to store a mode and always sets
+/// timestamps to the current time, so this is how the attributes from an
+/// archive survive a round-trip. Rewriting a file drops its stored
+/// modification time, so the underlying filesystem's timestamp is used again.
+#[derive(Debug, Clone)]
+pub(crate) struct AttributeFileSystem {
+    inner: Arc<dyn FileSystem + Send + Sync>,
+    attributes: Arc<Mutex<BTreeMap<PathBuf, Attributes>>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Attributes {
+    /// The permission bits (e.g. `0o755`).
+    pub mode: u32,
+    /// When the entry was last modified, in nanoseconds since the Unix epoch.
+    pub modified: Option<u64>,
+}
+
+impl AttributeFileSystem {
+    pub(crate) fn new(inner: Arc<dyn FileSystem + Send + Sync>) -> Self {
+        AttributeFileSystem {
+            inner,
+            attributes: Arc::default(),
+        }
+    }
+
+    pub(crate) fn set_attributes(&self, path: &Path, attributes: Attributes) {
+        self.attributes
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), attributes);
+    }
+
+    /// The permission bits for an entry, if they are known.
+    pub(crate) fn mode(&self, path: &Path) -> Option<u32> {
+        self.attributes.lock().unwrap().get(path).map(|a| a.mode)
+    }
+
+    fn apply(&self, path: &Path, mut metadata: virtual_fs::Metadata) -> virtual_fs::Metadata {
+        let attributes = self.attributes.lock().unwrap();
+        if let Some(modified) = attributes.get(path).and_then(|a| a.modified) {
+            metadata.modified = modified;
+        }
+        metadata
+    }
+
+    /// Forget the attributes for `path` and everything underneath it.
+    fn forget(&self, path: &Path) {
+        self.attributes
+            .lock()
+            .unwrap()
+            .retain(|p, _| !p.starts_with(path));
+    }
+}
+
+impl FileSystem for AttributeFileSystem {
+    fn read_dir(&self, path: &Path) -> virtual_fs::Result<virtual_fs::ReadDir> {
+        let entries = self
+            .inner
+            .read_dir(path)?
+            .map(|entry| {
+                let mut entry = entry?;
+                entry.metadata = entry.metadata.map(|m| self.apply(&entry.path, m));
+                Ok(entry)
+            })
+            .collect::<virtual_fs::Result<Vec<_>>>()?;
+
+        Ok(virtual_fs::ReadDir::new(entries))
+    }
+
+    fn create_dir(&self, path: &Path) -> virtual_fs::Result<()> {
+        self.inner.create_dir(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> virtual_fs::Result<()> {
+        self.inner.remove_dir(path)?;
+        self.forget(path);
+        Ok(())
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, virtual_fs::Result<()>> {
+        Box::pin(async move {
+            self.inner.rename(from, to).await?;
+
+            let mut attributes = self.attributes.lock().unwrap();
+            attributes.retain(|p, _| !p.starts_with(to));
+            let moved: Vec<PathBuf> = attributes
+                .keys()
+                .filter(|p| p.starts_with(from))
+                .cloned()
+                .collect();
+            for path in moved {
+                let value = attributes.remove(&path).unwrap();
+                let relative = path.strip_prefix(from).unwrap();
+                attributes.insert(to.join(relative), value);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn metadata(&self, path: &Path) -> virtual_fs::Result<virtual_fs::Metadata> {
+        let metadata = self.inner.metadata(path)?;
+        Ok(self.apply(path, metadata))
+    }
+
+    fn remove_file(&self, path: &Path) -> virtual_fs::Result<()> {
+        self.inner.remove_file(path)?;
+        self.forget(path);
+        Ok(())
+    }
+
+    fn new_open_options(&self) -> virtual_fs::OpenOptions {
+        virtual_fs::OpenOptions::new(self)
+    }
+}
+
+impl virtual_fs::FileOpener for AttributeFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &virtual_fs::OpenOptionsConfig,
+    ) -> virtual_fs::Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        let f = self
+            .inner
+            .new_open_options()
+            .options(conf.clone())
+            .open(path)?;
+
+        if conf.would_mutate() {
+            if let Some(attributes) = self.attributes.lock().unwrap().get_mut(path) {
+                attributes.modified = None;
+            }
+        }
+
+        Ok(f)
+    }
+}
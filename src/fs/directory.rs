@@ -6,16 +6,35 @@ use std::{
 use anyhow::Context;
 use js_sys::Reflect;
 use tracing::Instrument;
-use virtual_fs::{AsyncReadExt, AsyncWriteExt, FileSystem, FileType};
+use virtual_fs::{
+    AsyncReadExt, AsyncWriteExt, FileSystem, FileType, OverlayFileSystem, Upcastable,
+};
 use wasm_bindgen::{prelude::wasm_bindgen, JsCast, JsValue};
 use wasmer_wasix::runtime::task_manager::InlineWaker;
+use web_sys::{FileSystemDirectoryHandle, ReadableStream, WritableStream};
+
+use crate::{
+    fs::{
+        archive::{self, CompressionMethod},
+        attributes::AttributeFileSystem,
+        opfs::OpfsFileSystem,
+        watch::{Event, WatchedFile, Watcher, Watchers},
+    },
+    utils::Error,
+    StringOrBytes,
+};
 
-use crate::{utils::Error, StringOrBytes};
+/// The filesystem used by [`Directory::overlay()`], where the primary
+/// [`Directory`] is the writable upper layer.
+type Overlay = OverlayFileSystem<Directory, [Directory; 1]>;
 
 /// A directory that can be mounted inside a WASIX instance.
 #[derive(Debug, Clone, wasm_bindgen_derive::TryFromJsValue)]
 #[wasm_bindgen]
-pub struct Directory(Arc<dyn FileSystem>);
+pub struct Directory {
+    fs: Arc<dyn FileSystem>,
+    watchers: Watchers,
+}
 
 #[wasm_bindgen]
 impl Directory {
@@ -25,12 +44,150 @@ impl Directory {
         match init {
             Some(init) => {
                 let fs = init.initialize()?;
-                Ok(Directory(fs))
+                Ok(Directory::from_raw_fs(fs))
             }
             None => Ok(Directory::default()),
         }
     }
 
+    /// Create a {@link Directory} backed by a
+    /// [`FileSystemDirectoryHandle`](https://developer.mozilla.org/en-US/docs/Web/API/FileSystemDirectoryHandle)
+    /// from the Origin Private File System.
+    ///
+    /// Files are read and written in place by a dedicated worker, so only the
+    /// directory's metadata is kept in memory. Changes are made in the
+    /// background; use {@link Directory.sync} to wait until they have been
+    /// persisted and to find out whether any of them failed.
+    #[wasm_bindgen(js_name = "fromHandle")]
+    pub async fn from_handle(handle: FileSystemDirectoryHandle) -> Result<Directory, Error> {
+        let path = crate::fs::opfs::resolve(&handle).await?;
+        Directory::open_opfs(path).await
+    }
+
+    /// Open a directory inside the
+    /// [Origin Private File System](https://developer.mozilla.org/en-US/docs/Web/API/File_System_API/Origin_private_file_system),
+    /// creating it if it doesn't already exist.
+    ///
+    /// Files written to this directory will survive page reloads. If no path
+    /// is provided, the root of the Origin Private File System is used.
+    pub async fn opfs(path: Option<String>) -> Result<Directory, Error> {
+        let path = match path {
+            Some(path) => crate::fs::opfs::path_segments(Path::new(&path))?,
+            None => Vec::new(),
+        };
+
+        Directory::open_opfs(path).await
+    }
+
+    /// Create a copy-on-write view of another {@link Directory}.
+    ///
+    /// Reads fall through to `lower`, which is never modified. Anything
+    /// written, created, or deleted through the overlay is recorded in
+    /// `upper` instead (deletions are stored as `.wh.<name>` "whiteout"
+    /// files), so the same base directory can be shared between many
+    /// instances without them seeing each other's changes.
+    ///
+    /// If no upper layer is provided, a new in-memory {@link Directory} is
+    /// used. Use {@link Directory.upper} to inspect the changes that have been
+    /// made, or simply drop the overlay to discard them.
+    pub fn overlay(lower: &Directory, upper: Option<Directory>) -> Directory {
+        let upper = upper.unwrap_or_default();
+        let fs: Overlay = OverlayFileSystem::new(upper, [lower.clone()]);
+        Directory::from_raw_fs(Arc::new(fs))
+    }
+
+    /// The layer that changes are written to, if this directory was created
+    /// using {@link Directory.overlay}.
+    #[wasm_bindgen(getter)]
+    pub fn upper(&self) -> Option<Directory> {
+        self.fs
+            .upcast_any_ref()
+            .downcast_ref::<Overlay>()
+            .map(|overlay| overlay.primary().clone())
+    }
+
+    /// Create a new {@link Directory} from the contents of a tar archive.
+    ///
+    /// Gzipped tarballs are detected and decompressed automatically. Each
+    /// entry's mode and modification time are preserved, and will be
+    /// reported by {@link Directory.stat}.
+    #[wasm_bindgen(js_name = "fromTar")]
+    pub async fn from_tar(bytes: js_sys::Uint8Array) -> Result<Directory, Error> {
+        let fs = crate::fs::archive::from_tar(&bytes.to_vec()).await?;
+        Ok(Directory::from_raw_fs(Arc::new(fs)))
+    }
+
+    /// Export the contents of this directory as a tar archive.
+    ///
+    /// If a path is provided, only that sub-directory will be included and
+    /// entries in the archive will be relative to it.
+    #[wasm_bindgen(js_name = "toTar")]
+    pub async fn to_tar(
+        &self,
+        path: Option<String>,
+        options: Option<TarOptions>,
+    ) -> Result<js_sys::Uint8Array, Error> {
+        let mut path = path.unwrap_or_default();
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+
+        let gzip = options.and_then(|opts| opts.gzip()).unwrap_or(false);
+        let tarball = crate::fs::archive::to_tar(self, path.as_ref(), gzip).await?;
+
+        Ok(js_sys::Uint8Array::from(tarball.as_slice()))
+    }
+
+    /// Create a new {@link Directory} from the contents of a zip archive.
+    ///
+    /// Both stored and deflated entries are supported. As with
+    /// {@link Directory.fromTar}, modes and modification times are preserved.
+    #[wasm_bindgen(js_name = "fromZip")]
+    pub async fn from_zip(bytes: js_sys::Uint8Array) -> Result<Directory, Error> {
+        let fs = crate::fs::archive::from_zip(&bytes.to_vec()).await?;
+        Ok(Directory::from_raw_fs(Arc::new(fs)))
+    }
+
+    /// Export the contents of this directory as a zip archive.
+    ///
+    /// If a path is provided, only that sub-directory will be included and
+    /// entries in the archive will be relative to it.
+    #[wasm_bindgen(js_name = "toZip")]
+    pub async fn to_zip(
+        &self,
+        path: Option<String>,
+        options: Option<ZipOptions>,
+    ) -> Result<js_sys::Uint8Array, Error> {
+        let mut path = path.unwrap_or_default();
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+
+        let compression = match options.and_then(|opts| opts.compression()).as_deref() {
+            None | Some("deflate") => CompressionMethod::Deflated,
+            Some("stored") => CompressionMethod::Stored,
+            Some(other) => {
+                return Err(Error::js(js_sys::TypeError::new(&format!(
+                    "Unknown compression method, \"{other}\""
+                ))));
+            }
+        };
+        let archive = crate::fs::archive::to_zip(self, path.as_ref(), compression).await?;
+
+        Ok(js_sys::Uint8Array::from(archive.as_slice()))
+    }
+
+    /// Wait until any changes made to this directory have been persisted.
+    ///
+    /// This is a no-op for in-memory directories.
+    pub async fn sync(&self) -> Result<(), Error> {
+        if let Some(opfs) = self.fs.upcast_any_ref().downcast_ref::<OpfsFileSystem>() {
+            opfs.sync().await?;
+        }
+
+        Ok(())
+    }
+
     /// Read the contents of a directory.
     #[wasm_bindgen(js_name = "readDir")]
     pub async fn read_dir(&self, mut path: String) -> Result<ListOfDirEntry, Error> {
@@ -106,14 +263,77 @@ impl Directory {
         Ok(string.into())
     }
 
+    /// Open a file for reading as a
+    /// [`ReadableStream`](https://developer.mozilla.org/en-US/docs/Web/API/ReadableStream).
+    ///
+    /// The file is read incrementally as the stream is consumed, so this is
+    /// preferable to {@link Directory.readFile} for large files.
+    #[wasm_bindgen(js_name = "openReadStream")]
+    pub async fn open_read_stream(&self, mut path: String) -> Result<ReadableStream, Error> {
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+
+        let f = self
+            .new_open_options()
+            .read(true)
+            .open(&path)
+            .with_context(|| format!("Unable to open \"{path}\""))?;
+
+        Ok(crate::streams::file_read_stream(f))
+    }
+
+    /// Open a file for writing as a
+    /// [`WritableStream`](https://developer.mozilla.org/en-US/docs/Web/API/WritableStream),
+    /// creating it if it doesn't already exist.
+    ///
+    /// The file is truncated unless `append` is set. Data is written to the
+    /// file as each chunk arrives, and it gets closed when the stream is.
+    #[wasm_bindgen(js_name = "openWriteStream")]
+    pub async fn open_write_stream(
+        &self,
+        mut path: String,
+        options: Option<WriteStreamOptions>,
+    ) -> Result<WritableStream, Error> {
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+
+        let append = options.and_then(|opts| opts.append()).unwrap_or(false);
+
+        let f = self
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(&path)
+            .with_context(|| format!("Unable to open \"{path}\""))?;
+
+        Ok(crate::streams::file_write_stream(f))
+    }
+
     /// Create a directory.
+    ///
+    /// If `recursive` is set, any missing parent directories will also be
+    /// created and it isn't an error for the directory to already exist.
     #[wasm_bindgen(js_name = "createDir")]
-    pub async fn create_dir(&self, mut path: String) -> Result<(), Error> {
+    pub async fn create_dir(
+        &self,
+        mut path: String,
+        options: Option<CreateDirOptions>,
+    ) -> Result<(), Error> {
         if !path.starts_with('/') {
             path.insert(0, '/');
         }
 
-        FileSystem::create_dir(self, path.as_ref())?;
+        let recursive = options.and_then(|opts| opts.recursive()).unwrap_or(false);
+
+        if recursive {
+            create_dir_all(self, path.as_ref())?;
+        } else {
+            FileSystem::create_dir(self, path.as_ref())?;
+        }
 
         Ok(())
     }
@@ -141,14 +361,161 @@ impl Directory {
 
         Ok(())
     }
+
+    /// Get information about a file or directory.
+    pub async fn stat(&self, mut path: String) -> Result<JsMetadata, Error> {
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+
+        let metadata = FileSystem::metadata(self, path.as_ref())?;
+
+        let entry_type = match metadata.ft {
+            FileType { dir: true, .. } => "dir",
+            FileType { file: true, .. } => "file",
+            _ => "unknown",
+        };
+
+        let mode = self.mode(path.as_ref()).unwrap_or(if metadata.is_dir() {
+            archive::DEFAULT_DIR_MODE
+        } else {
+            archive::DEFAULT_FILE_MODE
+        });
+
+        let stat = js_sys::Object::new();
+        Reflect::set(&stat, &"type".into(), &entry_type.into()).map_err(Error::js)?;
+        Reflect::set(&stat, &"mode".into(), &JsValue::from(mode)).map_err(Error::js)?;
+        Reflect::set(&stat, &"size".into(), &JsValue::from(metadata.len as f64))
+            .map_err(Error::js)?;
+        Reflect::set(&stat, &"created".into(), &timestamp(metadata.created)).map_err(Error::js)?;
+        Reflect::set(&stat, &"modified".into(), &timestamp(metadata.modified))
+            .map_err(Error::js)?;
+        Reflect::set(&stat, &"accessed".into(), &timestamp(metadata.accessed))
+            .map_err(Error::js)?;
+
+        Ok(stat.unchecked_into())
+    }
+
+    /// Check whether a file or directory exists.
+    pub async fn exists(&self, mut path: String) -> Result<bool, Error> {
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+
+        match FileSystem::metadata(self, path.as_ref()) {
+            Ok(_) => Ok(true),
+            Err(virtual_fs::FsError::EntryNotFound) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Move a file or directory to a new location.
+    pub async fn rename(&self, mut from: String, mut to: String) -> Result<(), Error> {
+        if !from.starts_with('/') {
+            from.insert(0, '/');
+        }
+        if !to.starts_with('/') {
+            to.insert(0, '/');
+        }
+
+        FileSystem::rename(self, from.as_ref(), to.as_ref())
+            .await
+            .with_context(|| format!("Unable to rename \"{from}\" to \"{to}\""))?;
+
+        Ok(())
+    }
+
+    /// Copy a file or directory (recursively) to a new location, overwriting
+    /// any files that already exist.
+    ///
+    /// Copying an entry onto itself does nothing.
+    pub async fn copy(&self, from: String, to: String) -> Result<(), Error> {
+        let from = normalize_path(&from);
+        let to = normalize_path(&to);
+
+        if from == to {
+            // Note: Opening the destination would truncate the source, so
+            // the only thing left to do is make sure it exists.
+            FileSystem::metadata(self, &from)
+                .with_context(|| format!("Unable to copy \"{}\"", from.display()))?;
+            return Ok(());
+        }
+
+        if to.starts_with(&from) {
+            // Note: Copying a directory into itself would recurse forever.
+            return Err(anyhow::Error::new(virtual_fs::FsError::InvalidInput)
+                .context(format!(
+                    "Unable to copy \"{}\" into itself, \"{}\"",
+                    from.display(),
+                    to.display()
+                ))
+                .into());
+        }
+
+        copy_recursive(self, &from, &to).await.with_context(|| {
+            format!(
+                "Unable to copy \"{}\" to \"{}\"",
+                from.display(),
+                to.display()
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// Call `callback` whenever a file or directory at `path` is created,
+    /// modified, deleted, or renamed.
+    ///
+    /// Changes made by WASIX programs this directory is mounted into are
+    /// reported as well as those made through this object. Events for
+    /// direct children of `path` are included, and setting `recursive` will
+    /// include everything underneath it.
+    ///
+    /// Call {@link Watcher.close} to stop receiving events.
+    pub fn watch(
+        &self,
+        mut path: String,
+        callback: WatchCallback,
+        options: Option<WatchOptions>,
+    ) -> Watcher {
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+
+        let recursive = options.and_then(|opts| opts.recursive()).unwrap_or(false);
+
+        self.watchers
+            .subscribe(path.into(), recursive, callback.unchecked_into())
+    }
 }
 
 impl Directory {
     pub fn from_raw_fs(fs: Arc<dyn FileSystem>) -> Self {
-        Self(fs)
+        Directory {
+            fs,
+            watchers: Watchers::default(),
+        }
+    }
+
+    /// The permission bits for an entry, if they are known.
+    ///
+    /// Only directories extracted from an archive keep track of these.
+    pub(crate) fn mode(&self, path: &Path) -> Option<u32> {
+        self.fs
+            .upcast_any_ref()
+            .downcast_ref::<AttributeFileSystem>()
+            .and_then(|fs| fs.mode(path))
+    }
+
+    async fn open_opfs(path: Vec<String>) -> Result<Directory, Error> {
+        let pool = crate::runtime::Runtime::lazily_initialized()?
+            .thread_pool()
+            .clone();
+        let fs = OpfsFileSystem::load(path, pool).await?;
+        Ok(Directory::from_raw_fs(Arc::new(fs)))
     }
 
-    async fn _read_file(&self, mut path: String) -> Result<Vec<u8>, Error> {
+    pub(crate) async fn _read_file(&self, mut path: String) -> Result<Vec<u8>, Error> {
         if !path.starts_with('/') {
             path.insert(0, '/');
         }
@@ -163,24 +530,28 @@ impl Directory {
 
 impl Default for Directory {
     fn default() -> Self {
-        Directory(Arc::new(virtual_fs::mem_fs::FileSystem::default()))
+        Directory::from_raw_fs(Arc::new(virtual_fs::mem_fs::FileSystem::default()))
     }
 }
 
 impl FileSystem for Directory {
     #[tracing::instrument(level = "trace", skip(self))]
     fn read_dir(&self, path: &std::path::Path) -> virtual_fs::Result<virtual_fs::ReadDir> {
-        self.0.read_dir(path)
+        self.fs.read_dir(path)
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn create_dir(&self, path: &std::path::Path) -> virtual_fs::Result<()> {
-        self.0.create_dir(path)
+        self.fs.create_dir(path)?;
+        self.watchers.notify(Event::Create(path.to_path_buf()));
+        Ok(())
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn remove_dir(&self, path: &std::path::Path) -> virtual_fs::Result<()> {
-        self.0.remove_dir(path)
+        self.fs.remove_dir(path)?;
+        self.watchers.notify(Event::Delete(path.to_path_buf()));
+        Ok(())
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
@@ -189,17 +560,29 @@ impl FileSystem for Directory {
         from: &'a std::path::Path,
         to: &'a std::path::Path,
     ) -> futures::future::BoxFuture<'a, virtual_fs::Result<()>> {
-        Box::pin(self.0.rename(from, to).in_current_span())
+        Box::pin(
+            async move {
+                self.fs.rename(from, to).await?;
+                self.watchers.notify(Event::Rename {
+                    from: from.to_path_buf(),
+                    to: to.to_path_buf(),
+                });
+                Ok(())
+            }
+            .in_current_span(),
+        )
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn metadata(&self, path: &std::path::Path) -> virtual_fs::Result<virtual_fs::Metadata> {
-        self.0.metadata(path)
+        self.fs.metadata(path)
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn remove_file(&self, path: &std::path::Path) -> virtual_fs::Result<()> {
-        self.0.remove_file(path)
+        self.fs.remove_file(path)?;
+        self.watchers.notify(Event::Delete(path.to_path_buf()));
+        Ok(())
     }
 
     fn new_open_options(&self) -> virtual_fs::OpenOptions {
@@ -214,7 +597,29 @@ impl virtual_fs::FileOpener for Directory {
         path: &std::path::Path,
         conf: &virtual_fs::OpenOptionsConfig,
     ) -> virtual_fs::Result<Box<dyn virtual_fs::VirtualFile + Send + Sync + 'static>> {
-        self.0.new_open_options().options(conf.clone()).open(path)
+        if !conf.would_mutate() {
+            return self.fs.new_open_options().options(conf.clone()).open(path);
+        }
+
+        let existed = self.fs.metadata(path).is_ok();
+        let inner = self
+            .fs
+            .new_open_options()
+            .options(conf.clone())
+            .open(path)?;
+
+        if !existed {
+            self.watchers.notify(Event::Create(path.to_path_buf()));
+        }
+
+        // Note: Opening an existing file with "truncate" modifies it
+        let dirty = existed && conf.truncate();
+        Ok(Box::new(WatchedFile::new(
+            inner,
+            path.to_path_buf(),
+            self.watchers.clone(),
+            dirty,
+        )))
     }
 }
 
@@ -241,6 +646,157 @@ extern "C" {
     pub type ListOfDirEntry;
 }
 
+#[wasm_bindgen(typescript_custom_section)]
+const METADATA_TYPE_DEF: &'static str = r#"
+/**
+ * Information about a file or directory, as returned by
+ * {@link Directory.stat}.
+ */
+export type Metadata = {
+    /**
+     * What type of entry is this?
+     */
+    type: "file" | "dir" | "unknown";
+    /**
+     * The item's Unix permissions (e.g. `0o755`).
+     *
+     * Only directories created from an archive track permissions, so
+     * everything else will report `0o644` for files and `0o755` for
+     * directories.
+     */
+    mode: number;
+    /**
+     * The size of the item, in bytes.
+     */
+    size: number;
+    /**
+     * When the item was created, in milliseconds since the Unix epoch.
+     */
+    created: number;
+    /**
+     * When the item was last modified, in milliseconds since the Unix epoch.
+     */
+    modified: number;
+    /**
+     * When the item was last accessed, in milliseconds since the Unix epoch.
+     */
+    accessed: number;
+};
+
+/**
+ * Options that can be passed to {@link Directory.createDir}.
+ */
+export type CreateDirOptions = {
+    /**
+     * Create any missing parent directories, and don't fail if the directory
+     * already exists.
+     */
+    recursive?: boolean;
+};
+
+/**
+ * Options that can be passed to {@link Directory.toTar}.
+ */
+export type TarOptions = {
+    /**
+     * Compress the archive using gzip.
+     */
+    gzip?: boolean;
+};
+
+/**
+ * Options that can be passed to {@link Directory.toZip}.
+ */
+export type ZipOptions = {
+    /**
+     * How each file should be compressed (defaults to `"deflate"`).
+     */
+    compression?: "stored" | "deflate";
+};
+
+/**
+ * Options that can be passed to {@link Directory.openWriteStream}.
+ */
+export type WriteStreamOptions = {
+    /**
+     * Add to the end of the file instead of overwriting it.
+     */
+    append?: boolean;
+};
+
+/**
+ * A change to a file or directory, as reported by {@link Directory.watch}.
+ */
+export type WatchEvent = {
+    type: "create" | "modify" | "delete";
+    path: string;
+} | {
+    type: "rename";
+    /**
+     * The entry's new location.
+     */
+    path: string;
+    /**
+     * Where the entry was renamed from.
+     */
+    from: string;
+};
+
+/**
+ * Options that can be passed to {@link Directory.watch}.
+ */
+export type WatchOptions = {
+    /**
+     * Also report changes to nested files and directories.
+     */
+    recursive?: boolean;
+};
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "Metadata")]
+    pub type JsMetadata;
+
+    #[wasm_bindgen(typescript_type = "TarOptions")]
+    pub type TarOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn gzip(this: &TarOptions) -> Option<bool>;
+
+    #[wasm_bindgen(typescript_type = "ZipOptions")]
+    pub type ZipOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn compression(this: &ZipOptions) -> Option<String>;
+
+    #[wasm_bindgen(typescript_type = "WriteStreamOptions")]
+    pub type WriteStreamOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn append(this: &WriteStreamOptions) -> Option<bool>;
+
+    #[wasm_bindgen(typescript_type = "(event: WatchEvent) => void")]
+    pub type WatchCallback;
+
+    #[wasm_bindgen(typescript_type = "WatchOptions")]
+    pub type WatchOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn recursive(this: &WatchOptions) -> Option<bool>;
+
+    #[wasm_bindgen(typescript_type = "CreateDirOptions")]
+    pub type CreateDirOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn recursive(this: &CreateDirOptions) -> Option<bool>;
+}
+
+/// Convert a timestamp in nanoseconds to the milliseconds JavaScript expects.
+fn timestamp(nanos: u64) -> JsValue {
+    JsValue::from(nanos as f64 / 1_000_000.0)
+}
+
 #[wasm_bindgen(typescript_custom_section)]
 const DIRECTORY_INIT_TYPE_DEF: &'static str = r#"
 /**
@@ -307,8 +863,58 @@ fn in_memory_filesystem(record: &js_sys::Object) -> Result<virtual_fs::mem_fs::F
     Ok(fs)
 }
 
+/// Turn a path into an absolute path, resolving any `.` and `..` components.
+fn normalize_path(path: &str) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+
+    for component in Path::new(path).components() {
+        match component {
+            std::path::Component::Normal(name) => normalized.push(name),
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => {}
+        }
+    }
+
+    normalized
+}
+
+/// Copy a file or directory within the same [`FileSystem`].
+fn copy_recursive<'a>(
+    fs: &'a dyn FileSystem,
+    from: &'a Path,
+    to: &'a Path,
+) -> futures::future::LocalBoxFuture<'a, Result<(), anyhow::Error>> {
+    Box::pin(async move {
+        let metadata = fs.metadata(from)?;
+
+        if metadata.is_dir() {
+            create_dir_all(fs, to)?;
+
+            for entry in fs.read_dir(from)? {
+                let entry = entry?;
+                let dest = to.join(entry.file_name());
+                copy_recursive(fs, &entry.path, &dest).await?;
+            }
+        } else {
+            let mut src = fs.new_open_options().read(true).open(from)?;
+            let mut dest = fs
+                .new_open_options()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(to)?;
+            tokio::io::copy(&mut src, &mut dest).await?;
+            dest.flush().await?;
+        }
+
+        Ok(())
+    })
+}
+
 #[tracing::instrument(level = "trace", skip(fs))]
-fn create_dir_all(fs: &dyn FileSystem, path: &Path) -> Result<(), anyhow::Error> {
+pub(crate) fn create_dir_all(fs: &dyn FileSystem, path: &Path) -> Result<(), anyhow::Error> {
     let ancestors: Vec<&Path> = path.ancestors().collect();
 
     for ancestor in ancestors.into_iter().rev() {
@@ -323,3 +929,21 @@ fn create_dir_all(fs: &dyn FileSystem, path: &Path) -> Result<(), anyhow::Error>
 
     Ok(())
 }
+
+/// Make sure `path` is an existing directory, creating it (and any missing
+/// parents) first if requested.
+pub(crate) fn ensure_dir(
+    fs: &dyn FileSystem,
+    path: &Path,
+    create: bool,
+) -> Result<(), anyhow::Error> {
+    if create {
+        create_dir_all(fs, path)?;
+    }
+
+    match fs.metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => Err(anyhow::Error::new(virtual_fs::FsError::BaseNotDirectory)),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,3 +1,15 @@
+mod archive;
+mod attributes;
 mod directory;
+mod opfs;
+mod readonly;
+mod watch;
 
-pub use self::directory::{Directory, DirectoryInit};
+pub(crate) use self::{
+    directory::{create_dir_all, ensure_dir},
+    readonly::ReadOnlyFileSystem,
+};
+pub use self::{
+    directory::{Directory, DirectoryInit},
+    watch::Watcher,
+};
@@ -0,0 +1,1200 @@
+//! A [`FileSystem`] backed by the browser's [Origin Private File System][opfs].
+//!
+//! The OPFS APIs are asynchronous, their handles can't be shared between
+//! threads, and sync access handles are only available inside workers,
+//! whereas [`FileSystem`] is synchronous and gets used from whichever thread
+//! a WASIX program happens to be running on. To bridge the two, all OPFS
+//! access goes through a [`Server`] running on a worker of its own.
+//!
+//! Files are opened with sync access handles and read or written in place.
+//! The only thing kept in memory is an [`Index`] with each entry's metadata,
+//! so directory listings and `stat()` calls can be answered synchronously.
+//!
+//! [opfs]: https://developer.mozilla.org/en-US/docs/Web/API/File_System_API/Origin_private_file_system
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    io::SeekFrom,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use anyhow::Context as _;
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{BoxFuture, LocalBoxFuture},
+    StreamExt,
+};
+use js_sys::{Reflect, Uint8Array};
+use tokio::io::ReadBuf;
+use tracing::Instrument;
+use virtual_fs::{
+    AsyncRead, AsyncSeek, AsyncWrite, FileSystem, FileType, FsError, Metadata, VirtualFile,
+};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    FileSystemDirectoryHandle, FileSystemFileHandle, FileSystemGetDirectoryOptions,
+    FileSystemGetFileOptions, FileSystemHandle, FileSystemHandleKind, FileSystemReadWriteOptions,
+    FileSystemRemoveOptions, FileSystemSyncAccessHandle,
+};
+
+use crate::{
+    tasks::ThreadPool,
+    utils::{Error, GlobalScope},
+};
+
+/// How much of a file gets copied at a time when renaming it.
+const COPY_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// A [`FileSystem`] that stores its contents in a directory inside the
+/// Origin Private File System.
+#[derive(Debug)]
+pub(crate) struct OpfsFileSystem {
+    index: Arc<Mutex<Index>>,
+    server: ServerHandle,
+    /// Keep the thread pool alive for as long as the [`Server`] is needed.
+    _pool: ThreadPool,
+}
+
+impl OpfsFileSystem {
+    /// Start serving the OPFS directory at `path` (relative to the OPFS root)
+    /// from a worker, loading the metadata for everything inside it.
+    #[tracing::instrument(level = "debug", skip(pool))]
+    pub(crate) async fn load(path: Vec<String>, pool: ThreadPool) -> Result<Self, Error> {
+        let (requests, receiver) = mpsc::unbounded();
+        let (loaded_tx, loaded_rx) = oneshot::channel();
+        let failure = Failure::default();
+
+        pool.spawn_dedicated(Box::new({
+            let failure = failure.clone();
+            move || -> LocalBoxFuture<'static, ()> {
+                Box::pin(
+                    async move {
+                        match Server::start(&path).await {
+                            Ok((server, index)) => {
+                                let _ = loaded_tx.send(Ok(index));
+                                server.run(receiver, failure).await;
+                            }
+                            Err(e) => {
+                                let _ = loaded_tx.send(Err(e.into_anyhow()));
+                            }
+                        }
+                    }
+                    .instrument(tracing::debug_span!("opfs")),
+                )
+            }
+        }))?;
+
+        let index = loaded_rx
+            .await
+            .context("The OPFS worker stopped before the directory was loaded")??;
+
+        Ok(OpfsFileSystem {
+            index: Arc::new(Mutex::new(index)),
+            server: ServerHandle { requests, failure },
+            _pool: pool,
+        })
+    }
+
+    /// Wait until every change made so far has been written to OPFS,
+    /// reporting any that couldn't be.
+    pub(crate) async fn sync(&self) -> Result<(), Error> {
+        self.server.call(Op::Barrier).await?;
+        self.server.check()?;
+
+        Ok(())
+    }
+
+    fn index(&self) -> std::sync::MutexGuard<'_, Index> {
+        self.index.lock().unwrap()
+    }
+}
+
+impl FileSystem for OpfsFileSystem {
+    fn read_dir(&self, path: &Path) -> virtual_fs::Result<virtual_fs::ReadDir> {
+        self.index().read_dir(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> virtual_fs::Result<()> {
+        self.index().create_dir(path)?;
+        self.server.notify(Op::CreateDir(path.to_path_buf()));
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> virtual_fs::Result<()> {
+        self.index().remove_dir(path)?;
+        self.server.notify(Op::Remove(path.to_path_buf()));
+        Ok(())
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, virtual_fs::Result<()>> {
+        Box::pin(async move {
+            self.index().rename(from, to)?;
+
+            if from == to {
+                // Note: The server moves entries by copying and then deleting
+                // them, which would destroy the original.
+                return Ok(());
+            }
+
+            self.server
+                .call(Op::Rename {
+                    from: from.to_path_buf(),
+                    to: to.to_path_buf(),
+                })
+                .await
+                .map_err(|e| {
+                    tracing::warn!(error = &*e, "Unable to rename a file in OPFS");
+                    FsError::IOError
+                })?;
+            Ok(())
+        })
+    }
+
+    fn metadata(&self, path: &Path) -> virtual_fs::Result<Metadata> {
+        self.index().metadata(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> virtual_fs::Result<()> {
+        self.index().remove_file(path)?;
+        self.server.notify(Op::Remove(path.to_path_buf()));
+        Ok(())
+    }
+
+    fn new_open_options(&self) -> virtual_fs::OpenOptions {
+        virtual_fs::OpenOptions::new(self)
+    }
+}
+
+impl virtual_fs::FileOpener for OpfsFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &virtual_fs::OpenOptionsConfig,
+    ) -> virtual_fs::Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        self.index().open(path, conf)?;
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        self.server.notify(Op::Open {
+            id,
+            path: path.to_path_buf(),
+            truncate: conf.truncate(),
+        });
+
+        Ok(Box::new(OpfsFile {
+            id,
+            path: path.to_path_buf(),
+            cursor: 0,
+            append: conf.append(),
+            index: Arc::clone(&self.index),
+            server: self.server.clone(),
+            pending: None,
+        }))
+    }
+}
+
+/// An open file, which gets read and written in place by the [`Server`].
+#[derive(Debug)]
+struct OpfsFile {
+    id: u64,
+    path: PathBuf,
+    cursor: u64,
+    append: bool,
+    index: Arc<Mutex<Index>>,
+    server: ServerHandle,
+    /// The read, write, or flush that is currently in flight.
+    pending: Option<oneshot::Receiver<Result<Vec<u8>, anyhow::Error>>>,
+}
+
+impl OpfsFile {
+    fn metadata(&self) -> Metadata {
+        self.index
+            .lock()
+            .unwrap()
+            .metadata(&self.path)
+            .unwrap_or_default()
+    }
+
+    /// Send `op` to the [`Server`] (unless a previous call already did) and
+    /// poll for its result.
+    fn poll_op(
+        &mut self,
+        cx: &mut Context<'_>,
+        op: impl FnOnce(&Self) -> Op,
+    ) -> Poll<std::io::Result<Vec<u8>>> {
+        if self.pending.is_none() {
+            let (reply, receiver) = oneshot::channel();
+            self.server.send(op(self), Some(reply));
+            self.pending = Some(receiver);
+        }
+
+        let receiver = self.pending.as_mut().unwrap();
+        let result = futures::ready!(Pin::new(receiver).poll(cx));
+        self.pending = None;
+
+        let result = match result {
+            Ok(Ok(data)) => Ok(data),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(anyhow::anyhow!("The OPFS worker has stopped")),
+        };
+
+        Poll::Ready(result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)))
+    }
+}
+
+impl Drop for OpfsFile {
+    fn drop(&mut self) {
+        self.server.notify(Op::Close { id: self.id });
+    }
+}
+
+impl VirtualFile for OpfsFile {
+    fn last_accessed(&self) -> u64 {
+        self.metadata().accessed
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.metadata().modified
+    }
+
+    fn created_time(&self) -> u64 {
+        self.metadata().created
+    }
+
+    fn size(&self) -> u64 {
+        self.metadata().len
+    }
+
+    fn set_len(&mut self, new_size: u64) -> virtual_fs::Result<()> {
+        self.index.lock().unwrap().resize(&self.path, new_size);
+        self.server.notify(Op::SetLen {
+            id: self.id,
+            len: new_size,
+        });
+        Ok(())
+    }
+
+    fn unlink(&mut self) -> BoxFuture<'static, virtual_fs::Result<()>> {
+        let result = self.index.lock().unwrap().remove_file(&self.path);
+        if result.is_ok() {
+            self.server.notify(Op::Remove(self.path.clone()));
+        }
+        Box::pin(async move { result })
+    }
+
+    fn poll_read_ready(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<usize>> {
+        let remaining = self.size().saturating_sub(self.cursor);
+        Poll::Ready(Ok(remaining as usize))
+    }
+
+    fn poll_write_ready(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(Ok(8192))
+    }
+}
+
+impl AsyncRead for OpfsFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let len = buf.remaining();
+        let data = futures::ready!(self.poll_op(cx, |f| Op::Read {
+            id: f.id,
+            offset: f.cursor,
+            len,
+        }))?;
+
+        // Note: The caller may have given us a smaller buffer this time
+        let bytes_read = data.len().min(buf.remaining());
+        buf.put_slice(&data[..bytes_read]);
+        self.cursor += bytes_read as u64;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for OpfsFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.append && self.pending.is_none() {
+            self.cursor = self.size();
+        }
+
+        futures::ready!(self.poll_op(cx, |f| Op::Write {
+            id: f.id,
+            offset: f.cursor,
+            data: buf.to_vec(),
+        }))?;
+
+        let end = self.cursor + buf.len() as u64;
+        self.index.lock().unwrap().wrote(&self.path, end);
+        self.cursor = end;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        futures::ready!(self.poll_op(cx, |f| Op::Flush { id: f.id }))?;
+        // Changes made in the background (e.g. creating the file) may have
+        // failed, so make sure the caller hears about it.
+        self.server
+            .check()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl AsyncSeek for OpfsFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        let cursor = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.cursor.checked_add_signed(offset),
+        };
+
+        self.cursor = cursor.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Ok(self.cursor))
+    }
+}
+
+/// The metadata for every file and directory being served.
+#[derive(Debug)]
+struct Index {
+    entries: BTreeMap<PathBuf, Metadata>,
+}
+
+impl Index {
+    fn new() -> Self {
+        let mut entries = BTreeMap::new();
+        entries.insert(PathBuf::from("/"), dir_metadata(now()));
+        Index { entries }
+    }
+
+    fn metadata(&self, path: &Path) -> virtual_fs::Result<Metadata> {
+        self.entries
+            .get(path)
+            .cloned()
+            .ok_or(FsError::EntryNotFound)
+    }
+
+    fn read_dir(&self, path: &Path) -> virtual_fs::Result<virtual_fs::ReadDir> {
+        if !self.metadata(path)?.is_dir() {
+            return Err(FsError::BaseNotDirectory);
+        }
+
+        let entries = self
+            .children(path)
+            .map(|(path, metadata)| virtual_fs::DirEntry {
+                path: path.clone(),
+                metadata: Ok(metadata.clone()),
+            })
+            .collect();
+
+        Ok(virtual_fs::ReadDir::new(entries))
+    }
+
+    /// Iterate over a directory's direct children.
+    fn children<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = (&'a PathBuf, &'a Metadata)> {
+        self.descendants(path)
+            .filter(move |(child, _)| child.parent() == Some(path))
+    }
+
+    /// Iterate over everything inside a directory, relying on paths being
+    /// ordered component-by-component.
+    fn descendants<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> impl Iterator<Item = (&'a PathBuf, &'a Metadata)> {
+        self.entries
+            .range(path.to_path_buf()..)
+            .skip_while(move |(p, _)| p.as_path() == path)
+            .take_while(move |(p, _)| p.starts_with(path))
+    }
+
+    fn check_parent(&self, path: &Path) -> virtual_fs::Result<()> {
+        let parent = path.parent().ok_or(FsError::InvalidInput)?;
+        if self.metadata(parent)?.is_dir() {
+            Ok(())
+        } else {
+            Err(FsError::BaseNotDirectory)
+        }
+    }
+
+    fn create_dir(&mut self, path: &Path) -> virtual_fs::Result<()> {
+        if self.entries.contains_key(path) {
+            return Err(FsError::AlreadyExists);
+        }
+        self.check_parent(path)?;
+
+        self.entries.insert(path.to_path_buf(), dir_metadata(now()));
+        Ok(())
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> virtual_fs::Result<()> {
+        if !self.metadata(path)?.is_dir() {
+            return Err(FsError::BaseNotDirectory);
+        }
+        if path.parent().is_none() {
+            return Err(FsError::PermissionDenied);
+        }
+        if self.children(path).next().is_some() {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+
+        self.entries.remove(path);
+        Ok(())
+    }
+
+    fn remove_file(&mut self, path: &Path) -> virtual_fs::Result<()> {
+        if !self.metadata(path)?.is_file() {
+            return Err(FsError::NotAFile);
+        }
+
+        self.entries.remove(path);
+        Ok(())
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> virtual_fs::Result<()> {
+        let metadata = self.metadata(from)?;
+        if from.parent().is_none() {
+            return Err(FsError::InvalidInput);
+        }
+        if from == to {
+            // Renaming something onto itself is a no-op
+            return Ok(());
+        }
+        if to.starts_with(from) {
+            return Err(FsError::InvalidInput);
+        }
+        self.check_parent(to)?;
+
+        if let Ok(existing) = self.metadata(to) {
+            match (metadata.is_dir(), existing.is_dir()) {
+                (true, true) if self.children(to).next().is_some() => {
+                    return Err(FsError::DirectoryNotEmpty);
+                }
+                (true, false) => return Err(FsError::BaseNotDirectory),
+                (false, true) => return Err(FsError::NotAFile),
+                _ => {}
+            }
+            self.entries.remove(to);
+        }
+
+        let moved: Vec<PathBuf> = std::iter::once(from.to_path_buf())
+            .chain(self.descendants(from).map(|(p, _)| p.clone()))
+            .collect();
+        for path in moved {
+            let metadata = self.entries.remove(&path).unwrap();
+            let relative = path.strip_prefix(from).unwrap();
+            self.entries.insert(to.join(relative), metadata);
+        }
+
+        Ok(())
+    }
+
+    fn open(
+        &mut self,
+        path: &Path,
+        conf: &virtual_fs::OpenOptionsConfig,
+    ) -> virtual_fs::Result<()> {
+        match self.entries.get_mut(path) {
+            Some(_) if conf.create_new() => Err(FsError::AlreadyExists),
+            Some(metadata) if metadata.is_dir() => Err(FsError::NotAFile),
+            Some(metadata) => {
+                if conf.truncate() {
+                    metadata.len = 0;
+                    metadata.modified = now();
+                }
+                Ok(())
+            }
+            None if conf.create() || conf.create_new() => {
+                self.check_parent(path)?;
+                self.entries
+                    .insert(path.to_path_buf(), file_metadata(0, now()));
+                Ok(())
+            }
+            None => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn resize(&mut self, path: &Path, len: u64) {
+        if let Some(metadata) = self.entries.get_mut(path) {
+            metadata.len = len;
+            metadata.modified = now();
+        }
+    }
+
+    /// Record that a file was written to, up to `end`.
+    fn wrote(&mut self, path: &Path, end: u64) {
+        if let Some(metadata) = self.entries.get_mut(path) {
+            metadata.len = metadata.len.max(end);
+            metadata.modified = now();
+        }
+    }
+}
+
+/// Errors from operations that ran in the background, which get reported by
+/// the next flush or [`OpfsFileSystem::sync()`].
+#[derive(Debug, Clone, Default)]
+struct Failure(Arc<Mutex<Option<anyhow::Error>>>);
+
+impl Failure {
+    fn record(&self, error: anyhow::Error) {
+        tracing::warn!(error = &*error, "Unable to persist a change to OPFS");
+
+        let mut failure = self.0.lock().unwrap();
+        if failure.is_none() {
+            *failure = Some(error);
+        }
+    }
+
+    fn take(&self) -> Option<anyhow::Error> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// A cheaply cloneable way to send requests to the [`Server`] from any
+/// thread.
+#[derive(Debug, Clone)]
+struct ServerHandle {
+    requests: mpsc::UnboundedSender<Request>,
+    failure: Failure,
+}
+
+impl ServerHandle {
+    fn send(&self, op: Op, reply: Option<oneshot::Sender<Result<Vec<u8>, anyhow::Error>>>) {
+        if let Err(e) = self.requests.unbounded_send(Request { op, reply }) {
+            let request = e.into_inner();
+            let error = anyhow::anyhow!("The OPFS worker has stopped");
+            match request.reply {
+                Some(reply) => {
+                    let _ = reply.send(Err(error));
+                }
+                None => self.failure.record(error),
+            }
+        }
+    }
+
+    /// Run an operation in the background, recording any errors so they can
+    /// be reported later.
+    fn notify(&self, op: Op) {
+        self.send(op, None);
+    }
+
+    async fn call(&self, op: Op) -> Result<Vec<u8>, anyhow::Error> {
+        let (reply, receiver) = oneshot::channel();
+        self.send(op, Some(reply));
+        receiver.await.context("The OPFS worker has stopped")?
+    }
+
+    /// Report the first background operation that failed since the last
+    /// check, if any.
+    fn check(&self) -> Result<(), anyhow::Error> {
+        match self.failure.take() {
+            Some(error) => Err(error.context("A previous change couldn't be saved to OPFS")),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Request {
+    op: Op,
+    /// Where to send the result, or [`None`] if any errors should be recorded
+    /// as a [`Failure`].
+    reply: Option<oneshot::Sender<Result<Vec<u8>, anyhow::Error>>>,
+}
+
+/// An operation for the [`Server`] to carry out.
+#[derive(Debug)]
+enum Op {
+    CreateDir(PathBuf),
+    /// Remove a file or directory (recursively).
+    Remove(PathBuf),
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Open {
+        id: u64,
+        path: PathBuf,
+        truncate: bool,
+    },
+    Read {
+        id: u64,
+        offset: u64,
+        len: usize,
+    },
+    Write {
+        id: u64,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    SetLen {
+        id: u64,
+        len: u64,
+    },
+    Flush {
+        id: u64,
+    },
+    Close {
+        id: u64,
+    },
+    /// Flush every open file.
+    Barrier,
+}
+
+/// The task that does all OPFS access on behalf of an [`OpfsFileSystem`].
+///
+/// Requests are handled one at a time and in order, so operations on the same
+/// file never race with each other.
+struct Server {
+    root: FileSystemDirectoryHandle,
+    /// Sync access handles for files that are currently open. Each handle
+    /// holds an exclusive lock on its file, so it is shared by everyone who
+    /// has the file open.
+    handles: HashMap<PathBuf, FileSystemSyncAccessHandle>,
+    /// The path each open file refers to.
+    files: HashMap<u64, PathBuf>,
+}
+
+impl Server {
+    async fn start(path: &[String]) -> Result<(Server, Index), Error> {
+        let storage = GlobalScope::current()
+            .storage()
+            .context("The Origin Private File System isn't available in this context")?;
+        let root: FileSystemDirectoryHandle = JsFuture::from(storage.get_directory())
+            .await
+            .map_err(Error::js)?
+            .unchecked_into();
+        let root = directory_handle(&root, &path.iter().collect::<PathBuf>(), true).await?;
+
+        let mut index = Index::new();
+        load_index(&root, Path::new("/"), &mut index).await?;
+        tracing::debug!(entries = index.entries.len(), "Loaded the OPFS directory");
+
+        let server = Server {
+            root,
+            handles: HashMap::new(),
+            files: HashMap::new(),
+        };
+
+        Ok((server, index))
+    }
+
+    async fn run(mut self, mut requests: mpsc::UnboundedReceiver<Request>, failure: Failure) {
+        while let Some(Request { op, reply }) = requests.next().await {
+            tracing::trace!(?op, "Handling request");
+
+            let result = self.handle(op).await.map_err(|e| e.into_anyhow());
+
+            match reply {
+                Some(reply) => {
+                    let _ = reply.send(result);
+                }
+                None => {
+                    if let Err(e) = result {
+                        failure.record(e);
+                    }
+                }
+            }
+        }
+
+        for (path, handle) in self.handles.drain() {
+            if let Err(e) = handle.flush() {
+                let e = crate::utils::js_error(e);
+                tracing::warn!(error = &*e, path = %path.display(), "Unable to flush a file");
+            }
+            handle.close();
+        }
+
+        tracing::debug!("All handles to the OPFS directory were dropped");
+    }
+
+    async fn handle(&mut self, op: Op) -> Result<Vec<u8>, Error> {
+        match op {
+            Op::CreateDir(path) => {
+                directory_handle(&self.root, &path, true).await?;
+            }
+            Op::Remove(path) => {
+                self.close_handles(&path);
+                self.files.retain(|_, p| !p.starts_with(&path));
+                remove_entry(&self.root, &path).await?;
+            }
+            Op::Rename { from, to } => {
+                self.close_handles(&from);
+                self.close_handles(&to);
+                move_entry(&self.root, &from, &to).await?;
+
+                for path in self.files.values_mut() {
+                    if let Ok(relative) = path.strip_prefix(&from) {
+                        *path = to.join(relative);
+                    }
+                }
+            }
+            Op::Open { id, path, truncate } => {
+                self.files.insert(id, path);
+                let handle = self.sync_handle(id).await?;
+                if truncate {
+                    handle.truncate_with_f64(0.0).map_err(Error::js)?;
+                }
+            }
+            Op::Read { id, offset, len } => {
+                let handle = self.sync_handle(id).await?;
+                // Note: the browser may not accept views into our (shared)
+                // linear memory, so we go through a buffer owned by JavaScript.
+                let buffer = Uint8Array::new_with_length(len as u32);
+                let bytes_read = handle
+                    .read_with_buffer_source_and_options(&buffer, &at(offset))
+                    .map_err(Error::js)?;
+                return Ok(buffer.subarray(0, bytes_read as u32).to_vec());
+            }
+            Op::Write { id, offset, data } => {
+                let handle = self.sync_handle(id).await?;
+                let buffer = Uint8Array::from(data.as_slice());
+                let bytes_written = handle
+                    .write_with_buffer_source_and_options(&buffer, &at(offset))
+                    .map_err(Error::js)?;
+                if bytes_written as usize != data.len() {
+                    let msg = format!("Only {bytes_written} of {} bytes were written", data.len());
+                    return Err(anyhow::Error::msg(msg).into());
+                }
+            }
+            Op::SetLen { id, len } => {
+                let handle = self.sync_handle(id).await?;
+                handle.truncate_with_f64(len as f64).map_err(Error::js)?;
+            }
+            Op::Flush { id } => {
+                if let Some(handle) = self.files.get(&id).and_then(|p| self.handles.get(p)) {
+                    handle.flush().map_err(Error::js)?;
+                }
+            }
+            Op::Close { id } => {
+                if let Some(path) = self.files.remove(&id) {
+                    if !self.files.values().any(|p| *p == path) {
+                        if let Some(handle) = self.handles.remove(&path) {
+                            let result = handle.flush();
+                            handle.close();
+                            result.map_err(Error::js)?;
+                        }
+                    }
+                }
+            }
+            Op::Barrier => {
+                for handle in self.handles.values() {
+                    handle.flush().map_err(Error::js)?;
+                }
+            }
+        }
+
+        Ok(Vec::new())
+    }
+
+    /// Get the sync access handle for an open file, opening it if necessary.
+    async fn sync_handle(&mut self, id: u64) -> Result<FileSystemSyncAccessHandle, Error> {
+        let path = self
+            .files
+            .get(&id)
+            .context("The file has been removed")?
+            .clone();
+
+        if let Some(handle) = self.handles.get(&path) {
+            return Ok(handle.clone());
+        }
+
+        let handle = open_sync_handle(&self.root, &path).await?;
+        self.handles.insert(path, handle.clone());
+
+        Ok(handle)
+    }
+
+    /// Release the locks on any files at or underneath `path`. They'll be
+    /// reopened the next time they are used.
+    fn close_handles(&mut self, path: &Path) {
+        self.handles.retain(|p, handle| {
+            if p.starts_with(path) {
+                let _ = handle.flush();
+                handle.close();
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+fn at(offset: u64) -> FileSystemReadWriteOptions {
+    let mut options = FileSystemReadWriteOptions::new();
+    options.at(offset as f64);
+    options
+}
+
+/// Recursively add the metadata for a directory's contents to the
+/// [`Index`].
+fn load_index<'a>(
+    handle: &'a FileSystemDirectoryHandle,
+    path: &'a Path,
+    index: &'a mut Index,
+) -> LocalBoxFuture<'a, Result<(), Error>> {
+    Box::pin(async move {
+        for child in entries(handle).await? {
+            let child_path = path.join(child.name());
+
+            match child.kind() {
+                FileSystemHandleKind::Directory => {
+                    index
+                        .entries
+                        .insert(child_path.clone(), dir_metadata(now()));
+                    load_index(child.unchecked_ref(), &child_path, index).await?;
+                }
+                FileSystemHandleKind::File => {
+                    let file_handle: &FileSystemFileHandle = child.unchecked_ref();
+                    let file: web_sys::File = JsFuture::from(file_handle.get_file())
+                        .await
+                        .map_err(Error::js)?
+                        .unchecked_into();
+                    let modified = (file.last_modified() * 1_000_000.0) as u64;
+                    index
+                        .entries
+                        .insert(child_path, file_metadata(file.size() as u64, modified));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    })
+}
+
+async fn open_sync_handle(
+    root: &FileSystemDirectoryHandle,
+    path: &Path,
+) -> Result<FileSystemSyncAccessHandle, Error> {
+    let (parent, name) = split_path(path)?;
+    let dir = directory_handle(root, parent, false).await?;
+    let file: FileSystemFileHandle = JsFuture::from(
+        dir.get_file_handle_with_options(name, FileSystemGetFileOptions::new().create(true)),
+    )
+    .await
+    .map_err(Error::js)?
+    .unchecked_into();
+
+    let handle = JsFuture::from(file.create_sync_access_handle())
+        .await
+        .map_err(Error::js)?
+        .unchecked_into();
+
+    Ok(handle)
+}
+
+/// Move a file or directory by copying it to its new location and removing
+/// the original.
+async fn move_entry(root: &FileSystemDirectoryHandle, from: &Path, to: &Path) -> Result<(), Error> {
+    match remove_entry(root, to).await {
+        Ok(()) => {}
+        Err(e) if is_not_found(&e) => {}
+        Err(e) => return Err(e),
+    }
+
+    copy_entry(root, from, to).await?;
+    remove_entry(root, from).await
+}
+
+fn copy_entry<'a>(
+    root: &'a FileSystemDirectoryHandle,
+    from: &'a Path,
+    to: &'a Path,
+) -> LocalBoxFuture<'a, Result<(), Error>> {
+    Box::pin(async move {
+        let (parent, name) = split_path(from)?;
+        let parent = directory_handle(root, parent, false).await?;
+        let is_dir = entries(&parent)
+            .await?
+            .iter()
+            .any(|e| e.name() == name && e.kind() == FileSystemHandleKind::Directory);
+
+        if is_dir {
+            let dir = directory_handle(root, from, false).await?;
+            directory_handle(root, to, true).await?;
+
+            for child in entries(&dir).await? {
+                let name = child.name();
+                copy_entry(root, &from.join(&name), &to.join(&name)).await?;
+            }
+        } else {
+            let src = open_sync_handle(root, from).await?;
+            let dest = match open_sync_handle(root, to).await {
+                Ok(dest) => dest,
+                Err(e) => {
+                    src.close();
+                    return Err(e);
+                }
+            };
+
+            let result = copy_file(&src, &dest);
+            src.close();
+            dest.close();
+            result?;
+        }
+
+        Ok(())
+    })
+}
+
+fn copy_file(
+    src: &FileSystemSyncAccessHandle,
+    dest: &FileSystemSyncAccessHandle,
+) -> Result<(), Error> {
+    let buffer = Uint8Array::new_with_length(COPY_CHUNK_SIZE);
+    let mut offset = 0.0;
+
+    dest.truncate_with_f64(0.0).map_err(Error::js)?;
+
+    loop {
+        let bytes_read = src
+            .read_with_buffer_source_and_options(&buffer, &at(offset as u64))
+            .map_err(Error::js)?;
+        if bytes_read == 0.0 {
+            break;
+        }
+
+        let chunk = buffer.subarray(0, bytes_read as u32);
+        dest.write_with_buffer_source_and_options(&chunk, &at(offset as u64))
+            .map_err(Error::js)?;
+        offset += bytes_read;
+    }
+
+    dest.flush().map_err(Error::js)?;
+
+    Ok(())
+}
+
+async fn remove_entry(root: &FileSystemDirectoryHandle, path: &Path) -> Result<(), Error> {
+    let (parent, name) = split_path(path)?;
+    let dir = directory_handle(root, parent, false).await?;
+    JsFuture::from(
+        dir.remove_entry_with_options(name, FileSystemRemoveOptions::new().recursive(true)),
+    )
+    .await
+    .map_err(Error::js)?;
+
+    Ok(())
+}
+
+fn is_not_found(error: &Error) -> bool {
+    match error {
+        Error::JavaScript(e) => e
+            .dyn_ref::<web_sys::DomException>()
+            .map_or(false, |e| e.name() == "NotFoundError"),
+        _ => false,
+    }
+}
+
+/// Get the handle for a nested directory, optionally creating any directories
+/// along the way.
+pub(crate) async fn directory_handle(
+    root: &FileSystemDirectoryHandle,
+    path: &Path,
+    create: bool,
+) -> Result<FileSystemDirectoryHandle, Error> {
+    let mut dir = root.clone();
+
+    for name in path_segments(path)? {
+        let mut options = FileSystemGetDirectoryOptions::new();
+        options.create(create);
+        dir = JsFuture::from(dir.get_directory_handle_with_options(&name, &options))
+            .await
+            .map_err(Error::js)?
+            .unchecked_into();
+    }
+
+    Ok(dir)
+}
+
+/// Split a path into the names of its components.
+pub(crate) fn path_segments(path: &Path) -> Result<Vec<String>, Error> {
+    let mut segments = Vec::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => segments.push(name.to_string_lossy().into_owned()),
+            Component::RootDir | Component::CurDir => continue,
+            _ => {
+                let msg = format!("Unsupported path component in \"{}\"", path.display());
+                return Err(Error::js(js_sys::TypeError::new(&msg)));
+            }
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Find where a directory handle lives inside the Origin Private File
+/// System, as a list of path components.
+pub(crate) async fn resolve(handle: &FileSystemDirectoryHandle) -> Result<Vec<String>, Error> {
+    let storage = GlobalScope::current()
+        .storage()
+        .context("The Origin Private File System isn't available in this context")?;
+    let root: FileSystemDirectoryHandle = JsFuture::from(storage.get_directory())
+        .await
+        .map_err(Error::js)?
+        .unchecked_into();
+
+    let path = JsFuture::from(root.resolve(handle))
+        .await
+        .map_err(Error::js)?;
+
+    if path.is_null() {
+        return Err(Error::js(js_sys::TypeError::new(
+            "Only directories inside the Origin Private File System are supported",
+        )));
+    }
+
+    crate::utils::js_string_array(path.unchecked_into())
+}
+
+fn split_path(path: &Path) -> Result<(&Path, &str), Error> {
+    let parent = path.parent().unwrap_or_else(|| Path::new("/"));
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("\"{}\" doesn't have a valid file name", path.display()))?;
+
+    Ok((parent, name))
+}
+
+/// Get all the entries in a directory.
+async fn entries(handle: &FileSystemDirectoryHandle) -> Result<Vec<FileSystemHandle>, Error> {
+    // Note: web-sys doesn't expose FileSystemDirectoryHandle.values() yet, so
+    // we need to look it up manually.
+    let values = Reflect::get(handle, &JsValue::from_str("values")).map_err(Error::js)?;
+    let values: js_sys::Function = values.dyn_into().map_err(Error::js)?;
+    let iterator: js_sys::AsyncIterator = values.call0(handle).map_err(Error::js)?.unchecked_into();
+
+    let done = JsValue::from_str("done");
+    let value = JsValue::from_str("value");
+    let mut entries = Vec::new();
+
+    loop {
+        let next = JsFuture::from(iterator.next().map_err(Error::js)?)
+            .await
+            .map_err(Error::js)?;
+
+        if Reflect::get(&next, &done).map_err(Error::js)?.is_truthy() {
+            break;
+        }
+
+        let entry = Reflect::get(&next, &value).map_err(Error::js)?;
+        entries.push(entry.unchecked_into());
+    }
+
+    Ok(entries)
+}
+
+fn dir_metadata(time: u64) -> Metadata {
+    Metadata {
+        ft: FileType {
+            dir: true,
+            ..Default::default()
+        },
+        accessed: time,
+        created: time,
+        modified: time,
+        len: 0,
+    }
+}
+
+fn file_metadata(len: u64, modified: u64) -> Metadata {
+    Metadata {
+        ft: FileType {
+            file: true,
+            ..Default::default()
+        },
+        accessed: modified,
+        created: modified,
+        modified,
+        len,
+    }
+}
+
+/// The current time, in nanoseconds since the Unix epoch.
+fn now() -> u64 {
+    (js_sys::Date::now() * 1_000_000.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn renaming_an_entry_onto_itself_is_a_no_op() {
+        let mut index = Index::new();
+        index.create_dir(Path::new("/dir")).unwrap();
+        index
+            .entries
+            .insert(PathBuf::from("/dir/file.txt"), file_metadata(5, 0));
+        index.create_dir(Path::new("/empty")).unwrap();
+
+        for path in ["/dir/file.txt", "/empty", "/dir"] {
+            index.rename(Path::new(path), Path::new(path)).unwrap();
+        }
+
+        let mut paths: Vec<_> = index
+            .entries
+            .keys()
+            .map(|p| p.display().to_string())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, ["/", "/dir", "/dir/file.txt", "/empty"]);
+        assert_eq!(index.metadata(Path::new("/dir/file.txt")).unwrap().len, 5);
+    }
+
+    #[wasm_bindgen_test]
+    fn cant_move_a_directory_inside_itself() {
+        let mut index = Index::new();
+        index.create_dir(Path::new("/dir")).unwrap();
+
+        let err = index
+            .rename(Path::new("/dir"), Path::new("/dir/nested"))
+            .unwrap_err();
+
+        assert_eq!(err, FsError::InvalidInput);
+        assert!(index.metadata(Path::new("/dir")).is_ok());
+    }
+}
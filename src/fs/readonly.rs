@@ -0,0 +1,65 @@
+use std::{path::Path, sync::Arc};
+
+use futures::future::BoxFuture;
+use virtual_fs::{FileSystem, FsError, VirtualFile};
+
+/// A [`FileSystem`] wrapper which rejects any attempt to modify the
+/// underlying filesystem.
+///
+/// WASIX programs will see these errors as `EACCES`.
+#[derive(Debug, Clone)]
+pub(crate) struct ReadOnlyFileSystem(Arc<dyn FileSystem + Send + Sync>);
+
+impl ReadOnlyFileSystem {
+    pub(crate) fn new(inner: Arc<dyn FileSystem + Send + Sync>) -> Self {
+        ReadOnlyFileSystem(inner)
+    }
+}
+
+impl FileSystem for ReadOnlyFileSystem {
+    fn read_dir(&self, path: &Path) -> virtual_fs::Result<virtual_fs::ReadDir> {
+        self.0.read_dir(path)
+    }
+
+    fn create_dir(&self, _path: &Path) -> virtual_fs::Result<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn remove_dir(&self, _path: &Path) -> virtual_fs::Result<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn rename<'a>(
+        &'a self,
+        _from: &'a Path,
+        _to: &'a Path,
+    ) -> BoxFuture<'a, virtual_fs::Result<()>> {
+        Box::pin(async { Err(FsError::PermissionDenied) })
+    }
+
+    fn metadata(&self, path: &Path) -> virtual_fs::Result<virtual_fs::Metadata> {
+        self.0.metadata(path)
+    }
+
+    fn remove_file(&self, _path: &Path) -> virtual_fs::Result<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn new_open_options(&self) -> virtual_fs::OpenOptions {
+        virtual_fs::OpenOptions::new(self)
+    }
+}
+
+impl virtual_fs::FileOpener for ReadOnlyFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &virtual_fs::OpenOptionsConfig,
+    ) -> virtual_fs::Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        if conf.would_mutate() {
+            return Err(FsError::PermissionDenied);
+        }
+
+        self.0.new_open_options().options(conf.clone()).open(path)
+    }
+}
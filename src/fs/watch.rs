@@ -0,0 +1,339 @@
+//! Notifying JavaScript code whenever the contents of a
+//! [`Directory`][super::Directory] change.
+//!
+//! Mutations can happen on any thread (e.g. a WASIX program running on a
+//! worker), but JavaScript callbacks can only be invoked from the thread that
+//! registered them. Each subscription therefore owns a channel, and a
+//! background task on the subscribing thread's event loop takes events off
+//! that channel and passes them to the callback.
+
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::{channel::mpsc, future::BoxFuture, StreamExt};
+use js_sys::Reflect;
+use tokio::io::ReadBuf;
+use virtual_fs::{AsyncRead, AsyncSeek, AsyncWrite, VirtualFile};
+use wasm_bindgen::{prelude::wasm_bindgen, JsValue};
+
+use crate::utils::Error;
+
+/// Something that happened to a file or directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Event {
+    Create(PathBuf),
+    Modify(PathBuf),
+    Delete(PathBuf),
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+impl Event {
+    fn paths(&self) -> impl Iterator<Item = &Path> {
+        let (first, second) = match self {
+            Event::Create(path) | Event::Modify(path) | Event::Delete(path) => (path, None),
+            Event::Rename { from, to } => (to, Some(from)),
+        };
+
+        std::iter::once(first.as_path()).chain(second.map(|p| p.as_path()))
+    }
+
+    fn to_js(&self) -> Result<JsValue, Error> {
+        let (ty, path, from) = match self {
+            Event::Create(path) => ("create", path, None),
+            Event::Modify(path) => ("modify", path, None),
+            Event::Delete(path) => ("delete", path, None),
+            Event::Rename { from, to } => ("rename", to, Some(from)),
+        };
+
+        let event = js_sys::Object::new();
+        Reflect::set(&event, &"type".into(), &ty.into()).map_err(Error::js)?;
+        Reflect::set(&event, &"path".into(), &path.display().to_string().into())
+            .map_err(Error::js)?;
+        if let Some(from) = from {
+            Reflect::set(&event, &"from".into(), &from.display().to_string().into())
+                .map_err(Error::js)?;
+        }
+
+        Ok(event.into())
+    }
+}
+
+/// The set of callbacks that are interested in changes to a directory.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Watchers(Arc<Mutex<State>>);
+
+#[derive(Debug, Default)]
+struct State {
+    last_id: u64,
+    subscriptions: Vec<Subscription>,
+}
+
+impl Watchers {
+    /// Start calling `callback` on the current thread whenever something at
+    /// (or, if `recursive` is set, underneath) `path` changes.
+    pub(crate) fn subscribe(
+        &self,
+        path: PathBuf,
+        recursive: bool,
+        callback: js_sys::Function,
+    ) -> Watcher {
+        let (sender, receiver) = mpsc::unbounded();
+
+        let id = {
+            let mut state = self.0.lock().unwrap();
+            state.last_id += 1;
+            let id = state.last_id;
+            state.subscriptions.push(Subscription {
+                id,
+                path,
+                recursive,
+                events: sender,
+            });
+            id
+        };
+
+        wasm_bindgen_futures::spawn_local(deliver_events(receiver, callback));
+
+        Watcher {
+            watchers: self.clone(),
+            id,
+        }
+    }
+
+    /// Let every interested subscriber know about an event.
+    pub(crate) fn notify(&self, event: Event) {
+        let mut state = self.0.lock().unwrap();
+
+        state.subscriptions.retain(|sub| {
+            if !event.paths().any(|path| sub.matches(path)) {
+                return !sub.events.is_closed();
+            }
+
+            sub.events.unbounded_send(event.clone()).is_ok()
+        });
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        let mut state = self.0.lock().unwrap();
+        state.subscriptions.retain(|sub| sub.id != id);
+    }
+}
+
+#[derive(Debug)]
+struct Subscription {
+    id: u64,
+    path: PathBuf,
+    recursive: bool,
+    events: mpsc::UnboundedSender<Event>,
+}
+
+impl Subscription {
+    fn matches(&self, path: &Path) -> bool {
+        if self.recursive {
+            path.starts_with(&self.path)
+        } else {
+            path == self.path || path.parent() == Some(self.path.as_path())
+        }
+    }
+}
+
+async fn deliver_events(mut events: mpsc::UnboundedReceiver<Event>, callback: js_sys::Function) {
+    while let Some(event) = events.next().await {
+        let result = event
+            .to_js()
+            .and_then(|e| callback.call1(&JsValue::NULL, &e).map_err(Error::js));
+
+        if let Err(e) = result {
+            let e = e.into_anyhow();
+            tracing::warn!(error = &*e, ?event, "The watch callback failed");
+        }
+    }
+}
+
+/// A handle to a callback registered with {@link Directory.watch}.
+#[derive(Debug)]
+#[wasm_bindgen]
+pub struct Watcher {
+    watchers: Watchers,
+    id: u64,
+}
+
+#[wasm_bindgen]
+impl Watcher {
+    /// Stop receiving events.
+    pub fn close(&self) {
+        self.watchers.unsubscribe(self.id);
+    }
+}
+
+/// A file that emits a [`Event::Modify`] whenever it is flushed or closed
+/// after being written to.
+#[derive(Debug)]
+pub(crate) struct WatchedFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    path: PathBuf,
+    watchers: Watchers,
+    dirty: bool,
+}
+
+impl WatchedFile {
+    pub(crate) fn new(
+        inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+        path: PathBuf,
+        watchers: Watchers,
+        dirty: bool,
+    ) -> Self {
+        WatchedFile {
+            inner,
+            path,
+            watchers,
+            dirty,
+        }
+    }
+
+    fn notify(&mut self) {
+        if std::mem::take(&mut self.dirty) {
+            self.watchers.notify(Event::Modify(self.path.clone()));
+        }
+    }
+}
+
+impl Drop for WatchedFile {
+    fn drop(&mut self) {
+        self.notify();
+    }
+}
+
+impl VirtualFile for WatchedFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> virtual_fs::Result<()> {
+        self.inner.set_len(new_size)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn unlink(&mut self) -> BoxFuture<'static, virtual_fs::Result<()>> {
+        self.dirty = false;
+        self.watchers.notify(Event::Delete(self.path.clone()));
+        self.inner.unlink()
+    }
+
+    fn poll_read_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut *self.inner).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut *self.inner).poll_write_ready(cx)
+    }
+}
+
+impl AsyncRead for WatchedFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for WatchedFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(bytes_written)) = result {
+            self.dirty |= bytes_written > 0;
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let result = Pin::new(&mut *self.inner).poll_flush(cx);
+        if let Poll::Ready(Ok(_)) = result {
+            self.notify();
+        }
+        result
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let result = Pin::new(&mut *self.inner).poll_shutdown(cx);
+        if let Poll::Ready(Ok(_)) = result {
+            self.notify();
+        }
+        result
+    }
+}
+
+impl AsyncSeek for WatchedFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
+        Pin::new(&mut *self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Pin::new(&mut *self.inner).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    fn subscription(path: &str, recursive: bool) -> Subscription {
+        let (events, _) = mpsc::unbounded();
+        Subscription {
+            id: 0,
+            path: PathBuf::from(path),
+            recursive,
+            events,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn non_recursive_watchers_only_see_direct_children() {
+        let sub = subscription("/dir", false);
+
+        assert!(sub.matches(Path::new("/dir")));
+        assert!(sub.matches(Path::new("/dir/file.txt")));
+        assert!(!sub.matches(Path::new("/dir/nested/file.txt")));
+        assert!(!sub.matches(Path::new("/directory")));
+    }
+
+    #[wasm_bindgen_test]
+    fn recursive_watchers_see_everything_underneath() {
+        let sub = subscription("/dir", true);
+
+        assert!(sub.matches(Path::new("/dir")));
+        assert!(sub.matches(Path::new("/dir/nested/file.txt")));
+        assert!(!sub.matches(Path::new("/other/file.txt")));
+    }
+}
@@ -1,12 +1,30 @@
-use futures::{channel::oneshot::Receiver, Stream, StreamExt, TryFutureExt};
-use js_sys::Uint8Array;
+use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{
+    channel::oneshot::{self, Receiver},
+    future::Shared,
+    stream::LocalBoxStream,
+    FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt,
+};
+use js_sys::{JsString, Uint8Array};
 use wasm_bindgen::{closure::Closure, prelude::wasm_bindgen, JsCast, JsValue};
-use wasmer_wasix::WasiRuntimeError;
+use wasmer_wasix::{
+    types::wasi::{Errno, ExitCode, Signal},
+    WasiEnvBuilder, WasiProcess, WasiRuntimeError,
+};
 
-use crate::utils::Error;
+use crate::{
+    streams::OutputKind,
+    tty::TtyState,
+    utils::{Error, GlobalScope},
+};
 
 /// A handle connected to a running WASIX program.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[wasm_bindgen]
 pub struct Instance {
     /// The standard input stream, if one wasn't provided when starting the
@@ -19,27 +37,124 @@ pub struct Instance {
     /// The WASI program's standard error.
     #[wasm_bindgen(getter_with_clone, readonly)]
     pub stderr: web_sys::ReadableStream,
-    pub(crate) exit: Receiver<ExitCondition>,
+    /// The WASI program's stdout and stderr interleaved in the order they
+    /// were written, as {@link OutputChunk}s. Only set when the instance was
+    /// started with `mergeStderr`.
+    #[wasm_bindgen(getter_with_clone, readonly)]
+    pub output: Option<web_sys::ReadableStream>,
+    pub(crate) exit: Shared<Receiver<ExitCondition>>,
+    pub(crate) process: ProcessHandle,
+    pub(crate) tty: TtyState,
+    /// Resolves once all output has been passed to the `onStdout` and
+    /// `onStderr` callbacks, if any were provided.
+    pub(crate) forwarded: Option<Shared<Receiver<()>>>,
 }
 
 #[wasm_bindgen]
 impl Instance {
     /// Wait for the process to exit.
+    ///
+    /// Use {@link WaitOptions} to limit how much output gets collected.
     #[wasm_bindgen(js_name = "wait")]
-    pub async fn js_wait(self) -> Result<JsOutput, Error> {
-        let output = self.wait().await?;
-        Ok(output.into())
+    pub fn js_wait(&self, options: Option<WaitOptions>) -> Result<OutputPromise, Error> {
+        let limits = match options {
+            Some(options) => OutputLimits::parse(&options)?,
+            None => OutputLimits::default(),
+        };
+
+        let instance = self.clone();
+        let promise = wasm_bindgen_futures::future_to_promise(async move {
+            let output = instance.wait(limits).await?;
+            Ok(JsOutput::from(output).into())
+        });
+
+        Ok(promise.unchecked_into())
+    }
+
+    /// Send a signal to the running program.
+    ///
+    /// Defaults to `"SIGTERM"` if no signal is provided. Signals sent before
+    /// the program has finished starting up will be delivered as soon as it
+    /// is running.
+    pub fn kill(&self, signal: Option<JsString>) -> Result<(), Error> {
+        let signal = match signal {
+            Some(name) => parse_signal(&String::from(name))?,
+            None => Signal::Sigterm,
+        };
+
+        self.process.signal(signal);
+
+        Ok(())
+    }
+
+    /// Change the size of the program's terminal and let it know by sending
+    /// a `SIGWINCH`.
+    ///
+    /// The size in pixels stays the same if it isn't provided.
+    pub fn resize(
+        &self,
+        cols: u32,
+        rows: u32,
+        pixel_width: Option<u32>,
+        pixel_height: Option<u32>,
+    ) {
+        self.tty.resize(cols, rows, pixel_width, pixel_height);
+        self.process.signal(Signal::Sigwinch);
     }
 }
 
 impl Instance {
+    /// Pass everything the program writes to stdout and stderr to the
+    /// provided callbacks on the current thread's event loop.
+    ///
+    /// Streams with a callback are locked, so [`Instance::wait()`] won't
+    /// collect anything from them.
+    pub(crate) fn forward_output(
+        &mut self,
+        on_stdout: Option<js_sys::Function>,
+        on_stderr: Option<js_sys::Function>,
+    ) {
+        if on_stdout.is_none() && on_stderr.is_none() {
+            return;
+        }
+
+        let chunks: LocalBoxStream<'static, Result<(OutputKind, Vec<u8>), Error>> =
+            match &self.output {
+                Some(output) => crate::streams::read_merged_output(output.clone()).boxed_local(),
+                None => {
+                    let mut streams = Vec::new();
+                    if on_stdout.is_some() {
+                        let stdout = crate::streams::read_to_end(self.stdout.clone());
+                        streams.push(stdout.map_ok(|d| (OutputKind::Stdout, d)).boxed_local());
+                    }
+                    if on_stderr.is_some() {
+                        let stderr = crate::streams::read_to_end(self.stderr.clone());
+                        streams.push(stderr.map_ok(|d| (OutputKind::Stderr, d)).boxed_local());
+                    }
+                    futures::stream::select_all(streams).boxed_local()
+                }
+            };
+
+        let (sender, receiver) = oneshot::channel();
+        wasm_bindgen_futures::spawn_local(async move {
+            forward_chunks(chunks, on_stdout, on_stderr).await;
+            let _ = sender.send(());
+        });
+
+        self.forwarded = Some(receiver.shared());
+    }
+
     #[tracing::instrument(skip_all)]
-    async fn wait(self) -> Result<Output, Error> {
+    async fn wait(self, limits: OutputLimits) -> Result<Output, Error> {
         let Instance {
             stdin,
             stdout,
             stderr,
+            output,
             exit,
+            process,
+            tty: _,
+            forwarded,
         } = self;
 
         if let Some(stdin) = stdin {
@@ -62,21 +177,73 @@ impl Instance {
             }
         }
 
-        let mut stdout_buffer = Vec::new();
-        let stdout_done = copy_to_buffer(crate::streams::read_to_end(stdout), &mut stdout_buffer);
-        let mut stderr_buffer = Vec::new();
-        let stderr_done = copy_to_buffer(crate::streams::read_to_end(stderr), &mut stderr_buffer);
+        let on_truncated = || {
+            if limits.kill {
+                tracing::debug!("Killing the process because it exceeded the output limit");
+                process.signal(Signal::Sigkill);
+            }
+        };
+        let stdout_buffer = RefCell::new(OutputBuffer::new(limits.max_bytes));
+        let stderr_buffer = RefCell::new(OutputBuffer::new(limits.max_bytes));
+
+        let stdout_done = copy_to_buffer(
+            crate::streams::read_to_end(stdout),
+            &stdout_buffer,
+            &on_truncated,
+        );
+        let stderr_done = copy_to_buffer(
+            crate::streams::read_to_end(stderr),
+            &stderr_buffer,
+            &on_truncated,
+        );
+        let output_done =
+            split_merged_output(output, &stdout_buffer, &stderr_buffer, &on_truncated);
 
         // Note: this relies on the underlying instance closing stdout and
         // stderr when it exits. Failing to do this will block forever.
-        let (_, _, ExitCondition(code)) =
-            futures::try_join!(stdout_done, stderr_done, exit.map_err(Error::from))?;
+        let (_, _, _, exit_condition) = futures::try_join!(
+            stdout_done,
+            stderr_done,
+            output_done,
+            exit.map_err(Error::from)
+        )?;
+
+        let stdout_buffer = stdout_buffer.into_inner();
+        let stderr_buffer = stderr_buffer.into_inner();
+
+        if let Some(forwarded) = forwarded {
+            // Make sure the callbacks have seen everything before we report
+            // that the program has exited.
+            let _ = forwarded.await;
+        }
+
+        let code = exit_condition.code();
+        let (reason, signal, trap, error) = match exit_condition {
+            ExitCondition::Exited(_) => (ExitReason::Exit, None, None, None),
+            ExitCondition::Signaled(signal) => (ExitReason::Signal, Some(signal as u8), None, None),
+            ExitCondition::Trapped { message, error } => {
+                (ExitReason::Trap, None, Some(message), Some(error))
+            }
+            ExitCondition::Failed(error) => (ExitReason::Error, None, None, Some(error)),
+            ExitCondition::TimedOut => {
+                return Err(Error::js(timeout_error(
+                    stdout_buffer.data,
+                    stderr_buffer.data,
+                )));
+            }
+        };
 
         let output = Output {
             code,
             ok: code == 0,
-            stdout: stdout_buffer,
-            stderr: stderr_buffer,
+            reason,
+            signal,
+            trap,
+            error,
+            stdout: stdout_buffer.data,
+            stdout_truncated: stdout_buffer.truncated,
+            stderr: stderr_buffer.data,
+            stderr_truncated: stderr_buffer.truncated,
         };
 
         Ok(output)
@@ -85,23 +252,188 @@ impl Instance {
 
 async fn copy_to_buffer(
     stream: impl Stream<Item = Result<Vec<u8>, Error>>,
-    buffer: &mut Vec<u8>,
+    buffer: &RefCell<OutputBuffer>,
+    on_truncated: &dyn Fn(),
 ) -> Result<(), Error> {
     futures::pin_mut!(stream);
     while let Some(chunk) = stream.next().await {
-        buffer.extend(chunk?);
+        if buffer.borrow_mut().extend(&chunk?) {
+            on_truncated();
+        }
+    }
+
+    Ok(())
+}
+
+/// How much output [`Instance::wait()`] should collect.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+struct OutputLimits {
+    /// The maximum number of bytes to keep from each of stdout and stderr.
+    max_bytes: Option<usize>,
+    /// Kill the process when a limit is reached instead of discarding the
+    /// rest of its output.
+    kill: bool,
+}
+
+impl OutputLimits {
+    fn parse(options: &WaitOptions) -> Result<Self, Error> {
+        let max_bytes = match options.max_output_bytes() {
+            Some(n) if n.is_finite() && n >= 0.0 => Some(n as usize),
+            Some(_) => {
+                return Err(Error::js(js_sys::RangeError::new(
+                    "maxOutputBytes must be a non-negative number",
+                )));
+            }
+            None => None,
+        };
+
+        let kill = match options.on_limit().as_deref() {
+            None | Some("truncate") => false,
+            Some("kill") => true,
+            Some(other) => {
+                return Err(Error::js(js_sys::TypeError::new(&format!(
+                    "Unsupported onLimit behaviour, \"{other}\""
+                ))));
+            }
+        };
+
+        Ok(OutputLimits { max_bytes, kill })
+    }
+}
+
+/// A buffer which stops growing once it reaches its limit.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct OutputBuffer {
+    data: Vec<u8>,
+    limit: Option<usize>,
+    truncated: bool,
+}
+
+impl OutputBuffer {
+    fn new(limit: Option<usize>) -> Self {
+        OutputBuffer {
+            data: Vec::new(),
+            limit,
+            truncated: false,
+        }
+    }
+
+    /// Add a chunk to the buffer, returning `true` the first time something
+    /// gets discarded.
+    fn extend(&mut self, chunk: &[u8]) -> bool {
+        let remaining = match self.limit {
+            Some(limit) => limit.saturating_sub(self.data.len()),
+            None => chunk.len(),
+        };
+        let len = remaining.min(chunk.len());
+        self.data.extend_from_slice(&chunk[..len]);
+
+        let newly_truncated = len < chunk.len() && !self.truncated;
+        self.truncated |= len < chunk.len();
+        newly_truncated
+    }
+}
+
+async fn forward_chunks(
+    chunks: impl Stream<Item = Result<(OutputKind, Vec<u8>), Error>>,
+    on_stdout: Option<js_sys::Function>,
+    on_stderr: Option<js_sys::Function>,
+) {
+    futures::pin_mut!(chunks);
+
+    while let Some(chunk) = chunks.next().await {
+        let (kind, data) = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let e = e.into_anyhow();
+                tracing::warn!(error = &*e, "Unable to read the program's output");
+                break;
+            }
+        };
+
+        let callback = match kind {
+            OutputKind::Stdout => &on_stdout,
+            OutputKind::Stderr => &on_stderr,
+        };
+        let Some(callback) = callback else {
+            continue;
+        };
+
+        if let Err(e) = call_output_callback(callback, &data).await {
+            let e = e.into_anyhow();
+            tracing::warn!(
+                error = &*e,
+                stream = kind.as_str(),
+                "The output callback failed"
+            );
+        }
+    }
+}
+
+/// Call an `onStdout` or `onStderr` callback, waiting for it to finish if it
+/// returns a promise.
+async fn call_output_callback(callback: &js_sys::Function, data: &[u8]) -> Result<(), Error> {
+    let ret = callback
+        .call1(&JsValue::NULL, &Uint8Array::from(data))
+        .map_err(Error::js)?;
+
+    if let Some(promise) = ret.dyn_ref::<js_sys::Promise>() {
+        wasm_bindgen_futures::JsFuture::from(promise.clone())
+            .await
+            .map_err(Error::js)?;
+    }
+
+    Ok(())
+}
+
+/// Read a merged output stream to completion, sorting each chunk into the
+/// buffer for the stream it was originally written to.
+async fn split_merged_output(
+    output: Option<web_sys::ReadableStream>,
+    stdout: &RefCell<OutputBuffer>,
+    stderr: &RefCell<OutputBuffer>,
+    on_truncated: &dyn Fn(),
+) -> Result<(), Error> {
+    let Some(output) = output else {
+        return Ok(());
+    };
+
+    let chunks = crate::streams::read_merged_output(output);
+    futures::pin_mut!(chunks);
+    while let Some(chunk) = chunks.next().await {
+        let (kind, data) = chunk?;
+        let buffer = match kind {
+            OutputKind::Stdout => stdout,
+            OutputKind::Stderr => stderr,
+        };
+        if buffer.borrow_mut().extend(&data) {
+            on_truncated();
+        }
     }
 
     Ok(())
 }
 
-#[derive(Debug)]
-pub(crate) struct ExitCondition(i32);
+/// How a WASIX program finished running.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ExitCondition {
+    /// The program exited normally with the provided exit code.
+    Exited(i32),
+    /// The program was terminated after being sent a signal.
+    Signaled(Signal),
+    /// The program was killed because it ran for longer than the configured
+    /// timeout.
+    TimedOut,
+    /// The program hit a WebAssembly trap (e.g. `unreachable` was executed).
+    Trapped { message: String, error: RunError },
+    /// The program couldn't be started, or failed for some other reason.
+    Failed(RunError),
+}
 
 impl ExitCondition {
     pub(crate) fn from_result(result: Result<(), anyhow::Error>) -> Self {
         let err = match result {
-            Ok(_) => return ExitCondition(0),
+            Ok(_) => return ExitCondition::Exited(0),
             Err(e) => e,
         };
 
@@ -111,13 +443,247 @@ impl ExitCondition {
             .find_map(|e| e.downcast_ref::<WasiRuntimeError>())
             .and_then(|runtime_error| runtime_error.as_exit_code());
 
-        match error_code {
-            Some(code) => ExitCondition(code.raw()),
-            None => {
-                tracing::debug!(error = &*err, "Process exited unexpectedly");
-                ExitCondition(1)
+        if let Some(code) = error_code {
+            return ExitCondition::Exited(code.raw());
+        }
+
+        tracing::debug!(error = &*err, "Process exited unexpectedly");
+
+        let trap = err
+            .chain()
+            .find_map(|e| e.downcast_ref::<wasmer::RuntimeError>())
+            .map(|trap| trap.message());
+
+        let error = RunError(Arc::new(err));
+        match trap {
+            Some(message) => ExitCondition::Trapped { message, error },
+            None => ExitCondition::Failed(error),
+        }
+    }
+
+    /// The exit code reported to JavaScript, using the shell convention of
+    /// `128 + signal` for programs that were killed by a signal.
+    pub(crate) fn code(&self) -> i32 {
+        match self {
+            ExitCondition::Exited(code) => *code,
+            ExitCondition::Signaled(signal) => 128 + *signal as i32,
+            ExitCondition::TimedOut => 128 + Signal::Sigkill as i32,
+            ExitCondition::Trapped { .. } | ExitCondition::Failed(_) => 1,
+        }
+    }
+}
+
+/// The error that caused a program to stop running.
+#[derive(Debug, Clone)]
+pub(crate) struct RunError(Arc<anyhow::Error>);
+
+impl PartialEq for RunError {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// The `reason` reported on an {@link Output}.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ExitReason {
+    Exit,
+    Signal,
+    Trap,
+    Error,
+}
+
+impl ExitReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            ExitReason::Exit => "exit",
+            ExitReason::Signal => "signal",
+            ExitReason::Trap => "trap",
+            ExitReason::Error => "error",
+        }
+    }
+}
+
+fn parse_signal(name: &str) -> Result<Signal, Error> {
+    match name {
+        "SIGTERM" => Ok(Signal::Sigterm),
+        "SIGINT" => Ok(Signal::Sigint),
+        "SIGKILL" => Ok(Signal::Sigkill),
+        other => Err(Error::js(js_sys::TypeError::new(&format!(
+            "Unsupported signal, \"{other}\""
+        )))),
+    }
+}
+
+/// A shared handle to the [`WasiProcess`] backing an [`Instance`].
+///
+/// The process only exists once the WASIX program has been instantiated on
+/// a worker, so any signals sent before then are queued up and delivered
+/// when the process is attached.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ProcessHandle(Arc<Mutex<ProcessState>>);
+
+#[derive(Debug, Default)]
+struct ProcessState {
+    process: Option<WasiProcess>,
+    pending: Vec<Signal>,
+    /// Every signal that was sent to the process, in order.
+    sent: Vec<Signal>,
+    timed_out: bool,
+    exited: bool,
+}
+
+impl ProcessHandle {
+    pub(crate) fn signal(&self, signal: Signal) {
+        let mut state = self.0.lock().unwrap();
+
+        if state.exited {
+            tracing::debug!(?signal, "Ignoring a signal sent after the process exited");
+            return;
+        }
+
+        // Note: Window size changes are just notifications, so they
+        // shouldn't be blamed for the process exiting.
+        if !matches!(signal, Signal::Sigwinch) {
+            state.sent.push(signal);
+        }
+
+        match &state.process {
+            Some(process) => deliver_signal(process, signal),
+            None => state.pending.push(signal),
+        }
+    }
+
+    /// Kill the process if it is still running once `timeout` has elapsed.
+    pub(crate) fn kill_after(&self, timeout: Duration) {
+        let handle = self.clone();
+        let milliseconds = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+
+        wasm_bindgen_futures::spawn_local(async move {
+            let sleep = GlobalScope::current().sleep(milliseconds);
+            if let Err(e) = wasm_bindgen_futures::JsFuture::from(sleep).await {
+                tracing::warn!(
+                    error = &*crate::utils::js_error(e),
+                    "Unable to start the timeout timer",
+                );
+                return;
+            }
+
+            {
+                let mut state = handle.0.lock().unwrap();
+                if state.exited {
+                    return;
+                }
+                state.timed_out = true;
             }
+
+            tracing::debug!(?timeout, "The process timed out");
+            handle.signal(Signal::Sigkill);
+        });
+    }
+
+    fn attach(&self, process: WasiProcess) {
+        let mut state = self.0.lock().unwrap();
+
+        for signal in state.pending.drain(..) {
+            deliver_signal(&process, signal);
         }
+
+        state.process = Some(process);
+    }
+
+    /// Mark the process as finished, returning the signals it was sent and
+    /// whether it timed out.
+    fn detach(&self) -> (Vec<Signal>, bool) {
+        let mut state = self.0.lock().unwrap();
+        state.process = None;
+        state.exited = true;
+        (std::mem::take(&mut state.sent), state.timed_out)
+    }
+}
+
+fn deliver_signal(process: &WasiProcess, signal: Signal) {
+    tracing::debug!(pid = %process.pid(), ?signal, "Sending a signal to the process");
+
+    match signal {
+        Signal::Sigkill => process.terminate(ExitCode::Errno(Errno::Canceled)),
+        other => process.signal_process(other),
+    }
+}
+
+/// Run a WASIX program to completion on the current thread, attaching its
+/// [`WasiProcess`] to the [`ProcessHandle`] so it can receive signals.
+///
+/// This blocks, so it should only ever be called from a worker.
+pub(crate) fn run_with_process_handle(
+    builder: WasiEnvBuilder,
+    module: wasmer::Module,
+    runtime: &dyn wasmer_wasix::Runtime,
+    handle: &ProcessHandle,
+) -> ExitCondition {
+    let mut store = runtime.new_store();
+
+    let result = (|| -> Result<(), anyhow::Error> {
+        let (instance, env) = builder.instantiate(module, &mut store)?;
+        handle.attach(env.data(&store).process.clone());
+
+        let start = instance.exports.get_function("_start")?;
+        env.data(&store).thread.set_status_running();
+        let result = wasmer_wasix::run_wasi_func_start(start, &mut store);
+
+        let exit_code = match &result {
+            Ok(_) => ExitCode::Errno(Errno::Success),
+            Err(e) => e.as_exit_code().unwrap_or(ExitCode::Errno(Errno::Noexec)),
+        };
+        env.on_exit(&mut store, Some(exit_code));
+
+        result.map_err(anyhow::Error::new)
+    })();
+
+    let (sent, timed_out) = handle.detach();
+
+    exit_condition(result, &sent, timed_out)
+}
+
+fn exit_condition(
+    result: Result<(), anyhow::Error>,
+    sent: &[Signal],
+    timed_out: bool,
+) -> ExitCondition {
+    match terminating_signal(&result, sent) {
+        // Note: The timer may fire after the program has already finished,
+        // so it only counts as a timeout if the SIGKILL is what stopped it.
+        Some(Signal::Sigkill) if timed_out => ExitCondition::TimedOut,
+        Some(signal) => ExitCondition::Signaled(signal),
+        None => ExitCondition::from_result(result),
+    }
+}
+
+/// Find the signal that ended a process, if it didn't exit (or crash) on
+/// its own.
+///
+/// Programs are free to handle signals and exit however they like, so we
+/// can only blame a signal when the process ended the way that signal's
+/// default action would have ended it.
+fn terminating_signal(result: &Result<(), anyhow::Error>, sent: &[Signal]) -> Option<Signal> {
+    let exit_code = result
+        .as_ref()
+        .err()?
+        .chain()
+        .find_map(|e| e.downcast_ref::<WasiRuntimeError>())
+        .and_then(|runtime_error| runtime_error.as_exit_code())?;
+
+    match exit_code {
+        // See deliver_signal()
+        ExitCode::Errno(Errno::Canceled) => {
+            sent.iter().copied().find(|s| matches!(s, Signal::Sigkill))
+        }
+        // The default action for these signals is to exit with EINTR
+        ExitCode::Errno(Errno::Intr) => sent
+            .iter()
+            .rev()
+            .copied()
+            .find(|s| matches!(s, Signal::Sigterm | Signal::Sigint)),
+        _ => None,
     }
 }
 
@@ -125,14 +691,32 @@ impl ExitCondition {
 struct Output {
     code: i32,
     ok: bool,
+    reason: ExitReason,
+    signal: Option<u8>,
+    trap: Option<String>,
+    error: Option<RunError>,
     stdout: Vec<u8>,
+    stdout_truncated: bool,
     stderr: Vec<u8>,
+    stderr_truncated: bool,
 }
 
 #[wasm_bindgen]
 extern "C" {
+    #[wasm_bindgen(typescript_type = "WaitOptions")]
+    pub type WaitOptions;
+
+    #[wasm_bindgen(method, getter, js_name = "maxOutputBytes")]
+    fn max_output_bytes(this: &WaitOptions) -> Option<f64>;
+
+    #[wasm_bindgen(method, getter, js_name = "onLimit")]
+    fn on_limit(this: &WaitOptions) -> Option<String>;
+
     #[wasm_bindgen(typescript_type = "Output")]
     pub type JsOutput;
+
+    #[wasm_bindgen(typescript_type = "Promise<Output>", extends = js_sys::Promise)]
+    pub type OutputPromise;
 }
 
 impl From<Output> for JsOutput {
@@ -140,13 +724,41 @@ impl From<Output> for JsOutput {
         let Output {
             code,
             ok,
+            reason,
+            signal,
+            trap,
+            error,
             stdout,
+            stdout_truncated,
             stderr,
+            stderr_truncated,
         } = value;
 
         let output = js_sys::Object::new();
         let _ = js_sys::Reflect::set(&output, &JsValue::from_str("code"), &JsValue::from(code));
         let _ = js_sys::Reflect::set(&output, &JsValue::from_str("ok"), &JsValue::from(ok));
+        let _ = js_sys::Reflect::set(
+            &output,
+            &JsValue::from_str("reason"),
+            &JsValue::from_str(reason.as_str()),
+        );
+        if let Some(signal) = signal {
+            let _ = js_sys::Reflect::set(
+                &output,
+                &JsValue::from_str("signal"),
+                &JsValue::from(signal),
+            );
+        }
+        if let Some(trap) = trap {
+            let _ = js_sys::Reflect::set(&output, &JsValue::from_str("trap"), &trap.into());
+        }
+        if let Some(RunError(error)) = error {
+            let _ = js_sys::Reflect::set(
+                &output,
+                &JsValue::from_str("error"),
+                &crate::utils::anyhow_to_js(&error),
+            );
+        }
         let _ = js_sys::Reflect::set(
             &output,
             &JsValue::from_str("stdoutBytes"),
@@ -157,6 +769,11 @@ impl From<Output> for JsOutput {
             &JsValue::from_str("stdout"),
             &lazily_decoded_string_property(stdout),
         );
+        let _ = js_sys::Reflect::set(
+            &output,
+            &JsValue::from_str("stdoutTruncated"),
+            &JsValue::from(stdout_truncated),
+        );
         let _ = js_sys::Reflect::set(
             &output,
             &JsValue::from_str("stderrBytes"),
@@ -167,11 +784,46 @@ impl From<Output> for JsOutput {
             &JsValue::from_str("stderr"),
             &lazily_decoded_string_property(stderr),
         );
+        let _ = js_sys::Reflect::set(
+            &output,
+            &JsValue::from_str("stderrTruncated"),
+            &JsValue::from(stderr_truncated),
+        );
 
         output.unchecked_into()
     }
 }
 
+/// Create the `TimeoutError` that [`Instance::wait()`] rejects with when the
+/// program was killed for exceeding its timeout.
+fn timeout_error(stdout: Vec<u8>, stderr: Vec<u8>) -> JsValue {
+    let error = js_sys::Error::new("The program exceeded its timeout and was killed");
+    error.set_name("TimeoutError");
+
+    let _ = js_sys::Reflect::set(
+        &error,
+        &JsValue::from_str("stdoutBytes"),
+        &Uint8Array::from(stdout.as_slice()),
+    );
+    js_sys::Object::define_property(
+        &error,
+        &JsValue::from_str("stdout"),
+        &lazily_decoded_string_property(stdout),
+    );
+    let _ = js_sys::Reflect::set(
+        &error,
+        &JsValue::from_str("stderrBytes"),
+        &Uint8Array::from(stderr.as_slice()),
+    );
+    js_sys::Object::define_property(
+        &error,
+        &JsValue::from_str("stderr"),
+        &lazily_decoded_string_property(stderr),
+    );
+
+    error.into()
+}
+
 fn lazily_decoded_string_property(binary: Vec<u8>) -> js_sys::Object {
     let contents: once_cell::unsync::Lazy<js_sys::JsString, _> =
         once_cell::unsync::Lazy::new(move || {
@@ -195,14 +847,74 @@ export type Output = {
     code: number;
     /* Did the program exit successfully? */
     ok: boolean;
+    /*
+     * Why the program stopped running.
+     *
+     * - `"exit"` - the program exited normally with {@link Output.code}
+     * - `"signal"` - the program was killed by {@link Output.signal}
+     * - `"trap"` - the program hit a WebAssembly trap, described by
+     *   {@link Output.trap}
+     * - `"error"` - the program couldn't be started or failed for some other
+     *   reason, described by {@link Output.error}
+     */
+    reason: "exit" | "signal" | "trap" | "error";
+    /*
+     * The number of the signal that terminated the program, if it was killed
+     * using {@link Instance.kill}.
+     */
+    signal?: number;
+    /* The trap message (e.g. "unreachable executed"), if the program trapped. */
+    trap?: string;
+    /*
+     * The error that stopped the program, if it trapped or failed. The
+     * `causes` property contains each error in the chain.
+     */
+    error?: Error & { detailedMessage: string; causes: string[] };
     /* The contents of the program's stdout stream. */
     stdoutBytes: Uint8Array;
     /* The program's stdout stream, decoded as UTF-8. */
     readonly stdout: string;
+    /* Was stdout cut short because it exceeded `maxOutputBytes`? */
+    stdoutTruncated: boolean;
     /* The contents of the program's stderr stream. */
     stderrBytes: Uint8Array;
     /* The program's stderr stream, decoded as UTF-8. */
     readonly stderr: string;
+    /* Was stderr cut short because it exceeded `maxOutputBytes`? */
+    stderrTruncated: boolean;
+}
+
+/**
+ * Options for {@link Instance.wait}.
+ */
+export type WaitOptions = {
+    /*
+     * The maximum number of bytes to collect from each of stdout and stderr.
+     * Anything beyond this is discarded.
+     */
+    maxOutputBytes?: number;
+    /*
+     * What to do once a stream exceeds `maxOutputBytes`. Either keep the
+     * program running and discard the excess output (`"truncate"`, the
+     * default), or kill it (`"kill"`).
+     */
+    onLimit?: "truncate" | "kill";
+}
+
+/**
+ * The error {@link Instance.wait} rejects with when a program runs for longer
+ * than the `timeout` it was started with.
+ */
+export type TimeoutError = Error & {
+    name: "TimeoutError";
+    /* Everything the program wrote to stdout before it was killed. */
+    stdoutBytes: Uint8Array;
+    /* The program's stdout stream, decoded as UTF-8. */
+    readonly stdout: string;
+    /* Everything the program wrote to stderr before it was killed. */
+    stderrBytes: Uint8Array;
+    /* The program's stderr stream, decoded as UTF-8. */
+    readonly stderr: string;
 }
 "#;
 
@@ -225,7 +937,11 @@ mod tests {
             stdin: Some(stdin_stream),
             stdout: stdout_stream,
             stderr: stderr_stream,
-            exit,
+            output: None,
+            exit: exit.shared(),
+            process: ProcessHandle::default(),
+            tty: TtyState::default(),
+            forwarded: None,
         };
         dbg!(&instance);
 
@@ -238,18 +954,24 @@ mod tests {
         // Now, we pretend the WASIX process exited
         stdout.close();
         stderr.close();
-        sender.send(ExitCondition(42)).unwrap();
+        sender.send(ExitCondition::Exited(42)).unwrap();
 
         // and wait for the result
-        let output = instance.wait().await.unwrap();
+        let output = instance.wait(OutputLimits::default()).await.unwrap();
 
         assert_eq!(
             output,
             Output {
                 code: 42,
                 ok: false,
+                reason: ExitReason::Exit,
+                signal: None,
+                trap: None,
+                error: None,
                 stdout: b"stdout".to_vec(),
-                stderr: b"stderr".to_vec()
+                stdout_truncated: false,
+                stderr: b"stderr".to_vec(),
+                stderr_truncated: false,
             }
         );
         // Reading from stdin should now result in an EOF because it's closed
@@ -257,4 +979,109 @@ mod tests {
         let bytes_read = stdin.read_to_end(&mut buffer).await.unwrap();
         assert_eq!(bytes_read, 0);
     }
+
+    #[wasm_bindgen_test]
+    async fn merged_output_is_split_when_waiting_for_completion() {
+        let (mut stdout, mut stderr, output) = crate::streams::merged_output_pipe();
+        let (sender, exit) = oneshot::channel();
+        let instance = Instance {
+            stdin: None,
+            stdout: crate::streams::closed_output_stream(),
+            stderr: crate::streams::closed_output_stream(),
+            output: Some(output),
+            exit: exit.shared(),
+            process: ProcessHandle::default(),
+            tty: TtyState::default(),
+            forwarded: None,
+        };
+
+        stdout.write_all(b"out 1,").await.unwrap();
+        stderr.write_all(b"err 1,").await.unwrap();
+        stdout.write_all(b"out 2").await.unwrap();
+        stderr.write_all(b"err 2").await.unwrap();
+        drop(stdout);
+        drop(stderr);
+        sender.send(ExitCondition::Exited(0)).unwrap();
+
+        let output = instance.wait(OutputLimits::default()).await.unwrap();
+
+        assert_eq!(output.stdout, b"out 1,out 2");
+        assert_eq!(output.stderr, b"err 1,err 2");
+    }
+
+    #[wasm_bindgen_test]
+    fn output_buffers_stop_growing_at_their_limit() {
+        let mut buffer = OutputBuffer::new(Some(5));
+
+        assert!(!buffer.extend(b"abc"));
+        assert!(buffer.extend(b"defg"));
+        assert!(!buffer.extend(b"hij"));
+
+        assert_eq!(buffer.data, b"abcde");
+        assert!(buffer.truncated);
+    }
+
+    #[wasm_bindgen_test]
+    fn killed_programs_use_the_shell_exit_code_convention() {
+        assert_eq!(ExitCondition::Exited(42).code(), 42);
+        assert_eq!(ExitCondition::Signaled(Signal::Sigint).code(), 130);
+        assert_eq!(ExitCondition::Signaled(Signal::Sigkill).code(), 137);
+    }
+
+    #[wasm_bindgen_test]
+    fn only_blame_signals_for_exits_they_caused() {
+        let exit = |code: ExitCode| -> Result<(), anyhow::Error> {
+            Err(WasiRuntimeError::Wasi(wasmer_wasix::WasiError::Exit(code)).into())
+        };
+
+        // The program handled SIGTERM and exited on its own
+        assert_eq!(
+            terminating_signal(&exit(ExitCode::Other(3)), &[Signal::Sigterm]),
+            None
+        );
+        // SIGTERM's default action
+        assert_eq!(
+            terminating_signal(&exit(ExitCode::Errno(Errno::Intr)), &[Signal::Sigterm]),
+            Some(Signal::Sigterm)
+        );
+        // The process was terminated by SIGKILL, even though SIGTERM came later
+        assert_eq!(
+            terminating_signal(
+                &exit(ExitCode::Errno(Errno::Canceled)),
+                &[Signal::Sigkill, Signal::Sigterm]
+            ),
+            Some(Signal::Sigkill)
+        );
+        assert_eq!(terminating_signal(&Ok(()), &[Signal::Sigint]), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn timeouts_only_count_if_the_program_was_killed() {
+        let killed: Result<(), anyhow::Error> = Err(WasiRuntimeError::Wasi(
+            wasmer_wasix::WasiError::Exit(ExitCode::Errno(Errno::Canceled)),
+        )
+        .into());
+
+        assert_eq!(
+            exit_condition(Ok(()), &[Signal::Sigkill], true),
+            ExitCondition::Exited(0)
+        );
+        assert_eq!(
+            exit_condition(killed, &[Signal::Sigkill], true),
+            ExitCondition::TimedOut
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn unexpected_errors_are_kept() {
+        let condition = ExitCondition::from_result(Err(anyhow::anyhow!("Missing import")));
+
+        match &condition {
+            ExitCondition::Failed(RunError(error)) => {
+                assert_eq!(error.to_string(), "Missing import");
+            }
+            other => panic!("Unexpected exit condition: {other:?}"),
+        }
+        assert_eq!(condition.code(), 1);
+    }
 }
@@ -4,8 +4,15 @@ use std::{
 };
 
 use wasm_bindgen::{prelude::wasm_bindgen, JsCast};
+use wasmer_wasix::runtime::module_cache::{ModuleCache, ThreadLocalCache};
 
-use crate::{runtime::Runtime, tasks::ThreadPool, utils::Error};
+use crate::{
+    module_cache::IndexedDbCache,
+    package_cache::{CacheStorageCache, MemoryCache, PackageCache},
+    runtime::Runtime,
+    tasks::ThreadPool,
+    utils::Error,
+};
 
 #[derive(Clone, Debug, wasm_bindgen_derive::TryFromJsValue)]
 #[repr(transparent)]
@@ -39,7 +46,7 @@ impl JsRuntime {
             None => Some(crate::DEFAULT_REGISTRY.to_string()),
         };
 
-        let mut rt = Runtime::new(pool);
+        let mut rt = Runtime::new(pool.clone());
 
         if let Some(registry) = registry.as_deref() {
             let api_key = options.as_ref().and_then(|opts| opts.api_key());
@@ -50,6 +57,14 @@ impl JsRuntime {
             rt.set_network_gateway(gateway);
         }
 
+        if let Some(cache) = options.as_ref().and_then(|opts| opts.package_cache()) {
+            rt.set_package_cache(cache.resolve(&pool)?);
+        }
+
+        if let Some(cache) = options.as_ref().and_then(|opts| opts.module_cache()) {
+            rt.set_module_cache(cache.resolve(&pool)?);
+        }
+
         Ok(JsRuntime::new(Arc::new(rt)))
     }
 
@@ -116,6 +131,72 @@ export type RuntimeOptions = {
      * Enable networking (i.e. TCP and UDP) via a gateway server.
      */
     networkGateway?: string;
+    /**
+     * Where downloaded packages should be cached.
+     *
+     * By default, packages are only cached in memory for as long as the
+     * {@link Runtime} is alive.
+     */
+    packageCache?: PackageCacheOptions;
+    /**
+     * Where compiled WebAssembly modules should be cached.
+     *
+     * By default, modules are only cached in memory and need to be compiled
+     * again after a page reload.
+     */
+    moduleCache?: ModuleCacheOptions;
+};
+
+/**
+ * Options for the cache used to store downloaded packages.
+ */
+export type PackageCacheOptions = {
+    /**
+     * Where packages are stored.
+     *
+     * - `"memory"` - in memory, for as long as the {@link Runtime} is alive
+     * - `"cache-storage"` - in the browser's Cache Storage, so packages don't
+     *   need to be downloaded again after a page reload
+     *
+     * Defaults to `"memory"`.
+     */
+    backend?: "memory" | "cache-storage";
+    /**
+     * The name of the cache packages are stored in.
+     *
+     * Defaults to `"wasmer-packages"`.
+     */
+    name?: string;
+    /**
+     * The maximum number of bytes to store. Once exceeded, the least recently
+     * used packages will be evicted.
+     *
+     * Defaults to 512 MiB.
+     */
+    maxSize?: number;
+};
+
+/**
+ * Options for the cache used to store compiled WebAssembly modules.
+ */
+export type ModuleCacheOptions = {
+    /**
+     * Where modules are stored.
+     *
+     * - `"memory"` - in memory, on each thread that uses them
+     * - `"indexed-db"` - in IndexedDB, so modules can be reused after a page
+     *   reload. Browsers that can't store a compiled `WebAssembly.Module`
+     *   will store the original `*.wasm` bytes instead.
+     *
+     * Defaults to `"memory"`.
+     */
+    backend?: "memory" | "indexed-db";
+    /**
+     * The name of the IndexedDB database modules are stored in.
+     *
+     * Defaults to `"wasmer-modules"`.
+     */
+    name?: string;
 };
 "#;
 
@@ -133,6 +214,33 @@ extern "C" {
     #[wasm_bindgen(method, getter, js_name = "networkGateway")]
     fn network_gateway(this: &RuntimeOptions) -> Option<String>;
 
+    #[wasm_bindgen(method, getter, js_name = "packageCache")]
+    fn package_cache(this: &RuntimeOptions) -> Option<PackageCacheOptions>;
+
+    #[wasm_bindgen(method, getter, js_name = "moduleCache")]
+    fn module_cache(this: &RuntimeOptions) -> Option<ModuleCacheOptions>;
+
+    #[wasm_bindgen(typescript_type = "PackageCacheOptions")]
+    type PackageCacheOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn backend(this: &PackageCacheOptions) -> Option<String>;
+
+    #[wasm_bindgen(method, getter)]
+    fn name(this: &PackageCacheOptions) -> Option<String>;
+
+    #[wasm_bindgen(method, getter, js_name = "maxSize")]
+    fn max_size(this: &PackageCacheOptions) -> Option<f64>;
+
+    #[wasm_bindgen(typescript_type = "ModuleCacheOptions")]
+    type ModuleCacheOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn backend(this: &ModuleCacheOptions) -> Option<String>;
+
+    #[wasm_bindgen(method, getter)]
+    fn name(this: &ModuleCacheOptions) -> Option<String>;
+
     #[wasm_bindgen(typescript_type = "string | null | undefined")]
     type MaybeRegistryUrl;
 }
@@ -150,3 +258,51 @@ impl MaybeRegistryUrl {
         }
     }
 }
+
+impl PackageCacheOptions {
+    fn resolve(&self, pool: &ThreadPool) -> Result<Arc<dyn PackageCache>, Error> {
+        let max_size = match self.max_size() {
+            Some(n) if n.is_finite() && n >= 0.0 => n as u64,
+            Some(_) => {
+                return Err(Error::js(js_sys::RangeError::new(
+                    "maxSize must be a non-negative number of bytes",
+                )));
+            }
+            None => crate::package_cache::DEFAULT_MAX_SIZE,
+        };
+
+        match self.backend().as_deref() {
+            None | Some("memory") => Ok(Arc::new(MemoryCache::default())),
+            Some("cache-storage") => {
+                let name = self
+                    .name()
+                    .unwrap_or_else(|| crate::package_cache::DEFAULT_CACHE_NAME.to_string());
+                Ok(Arc::new(CacheStorageCache::new(
+                    pool.clone(),
+                    name,
+                    max_size,
+                )))
+            }
+            Some(other) => Err(Error::js(js_sys::TypeError::new(&format!(
+                "Unknown package cache backend, \"{other}\""
+            )))),
+        }
+    }
+}
+
+impl ModuleCacheOptions {
+    fn resolve(&self, pool: &ThreadPool) -> Result<Arc<dyn ModuleCache + Send + Sync>, Error> {
+        match self.backend().as_deref() {
+            None | Some("memory") => Ok(Arc::new(ThreadLocalCache::default())),
+            Some("indexed-db") => {
+                let name = self
+                    .name()
+                    .unwrap_or_else(|| crate::module_cache::DEFAULT_DATABASE_NAME.to_string());
+                Ok(Arc::new(IndexedDbCache::new(pool.clone(), name)))
+            }
+            Some(other) => Err(Error::js(js_sys::TypeError::new(&format!(
+                "Unknown module cache backend, \"{other}\""
+            )))),
+        }
+    }
+}
@@ -7,13 +7,17 @@ pub mod fs;
 mod instance;
 mod js_runtime;
 mod logging;
+mod manifest;
+mod module_cache;
 mod net;
 mod options;
+mod package_cache;
 mod package_loader;
 mod run;
 mod runtime;
 mod streams;
 mod tasks;
+mod tty;
 mod utils;
 mod wasmer;
 mod ws;
@@ -21,7 +25,7 @@ mod ws;
 use std::sync::Mutex;
 
 pub use crate::{
-    fs::{Directory, DirectoryInit},
+    fs::{Directory, DirectoryInit, Watcher},
     instance::{Instance, JsOutput},
     js_runtime::{JsRuntime, RuntimeOptions},
     logging::initialize_logger,
@@ -0,0 +1,369 @@
+//! Building packages from a `wasmer.toml` file.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Error};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use virtual_fs::{FileSystem, FileType};
+use wasmer_wasix::runtime::resolver::WebcHash;
+use webc::{
+    metadata::{
+        annotations::{FileSystemMapping, FileSystemMappings},
+        Atom, Command, Manifest, UrlOrManifest,
+    },
+    v2::{
+        write::{DirEntry, Directory as Volume, FileEntry, Writer},
+        ChecksumAlgorithm, SignatureAlgorithm,
+    },
+    PathSegment,
+};
+
+use crate::fs::Directory;
+
+const WASI_RUNNER: &str = "https://webc.org/runner/wasi";
+const WASM_ATOM_KIND: &str = "https://webc.org/kind/wasm";
+
+/// Build an in-memory `*.webc` file from a [`Directory`] containing a
+/// `wasmer.toml` file, plus the modules and volumes it refers to.
+pub(crate) async fn build_webc(dir: &Directory) -> Result<Bytes, Error> {
+    let wasmer_toml = read_file(dir, "wasmer.toml").await?;
+    let wasmer_toml = String::from_utf8(wasmer_toml).context("wasmer.toml isn't valid UTF-8")?;
+    let wasmer_toml: WasmerToml =
+        toml::from_str(&wasmer_toml).context("Unable to parse wasmer.toml")?;
+
+    let mut atoms = BTreeMap::new();
+    for module in &wasmer_toml.module {
+        let wasm = read_file(dir, &module.source)
+            .await
+            .with_context(|| format!("Unable to load the \"{}\" module", module.name))?;
+        atoms.insert(module.name.clone(), Bytes::from(wasm));
+    }
+
+    let mut volumes = BTreeMap::new();
+    for host_path in wasmer_toml.fs.values() {
+        let volume = read_volume(dir, host_path)
+            .await
+            .with_context(|| format!("Unable to load the \"{host_path}\" directory"))?;
+        volumes.insert(volume_name(host_path), volume);
+    }
+
+    let manifest = wasmer_toml.to_manifest(&atoms)?;
+
+    let atoms = atoms
+        .into_iter()
+        .map(|(name, wasm)| Ok((PathSegment::parse(&name)?, FileEntry::Owned(wasm))))
+        .collect::<Result<BTreeMap<_, _>, Error>>()?;
+
+    let mut writer = Writer::new(ChecksumAlgorithm::Sha256)
+        .write_manifest(&manifest)?
+        .write_atoms(atoms)?;
+    for (name, volume) in volumes {
+        writer.write_volume(&name, volume)?;
+    }
+
+    Ok(writer.finish(SignatureAlgorithm::None)?)
+}
+
+async fn read_file(dir: &Directory, path: &str) -> Result<Vec<u8>, Error> {
+    dir._read_file(path.to_string())
+        .await
+        .map_err(|e| e.into_anyhow())
+        .with_context(|| format!("Unable to read \"{path}\""))
+}
+
+/// Recursively read a directory's contents into a webc volume.
+async fn read_volume(dir: &Directory, path: &str) -> Result<Volume<'static>, Error> {
+    let root = Path::new("/").join(path);
+    let mut volume = Volume::default();
+
+    let mut pending = vec![root.clone()];
+    while let Some(current) = pending.pop() {
+        for entry in FileSystem::read_dir(dir, &current)? {
+            let entry = entry?;
+            let relative = entry.path.strip_prefix(&root)?;
+            let segments = relative
+                .iter()
+                .map(|s| PathSegment::parse(&s.to_string_lossy()))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            match entry.file_type()? {
+                FileType { dir: true, .. } => {
+                    insert(&mut volume, &segments, None);
+                    pending.push(entry.path);
+                }
+                FileType { file: true, .. } => {
+                    let contents = read_file(dir, &entry.path.to_string_lossy()).await?;
+                    insert(&mut volume, &segments, Some(contents.into()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(volume)
+}
+
+/// Add a file (or an empty directory if there are no `contents`) to a
+/// volume, creating any parent directories as necessary.
+fn insert(dir: &mut Volume<'static>, path: &[PathSegment], contents: Option<Bytes>) {
+    match path {
+        [] => {}
+        [name] => {
+            let entry = match contents {
+                Some(contents) => DirEntry::File(FileEntry::Owned(contents)),
+                None => DirEntry::Dir(Volume::default()),
+            };
+            dir.children.entry(name.clone()).or_insert(entry);
+        }
+        [name, rest @ ..] => {
+            let entry = dir
+                .children
+                .entry(name.clone())
+                .or_insert_with(|| DirEntry::Dir(Volume::default()));
+            if let DirEntry::Dir(child) = entry {
+                insert(child, rest, contents);
+            }
+        }
+    }
+}
+
+fn volume_name(host_path: &str) -> String {
+    let path: PathBuf = Path::new("/").join(host_path).components().collect();
+    path.display().to_string()
+}
+
+/// The subset of the `wasmer.toml` format we know how to build.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct WasmerToml {
+    package: PackageSection,
+    #[serde(default)]
+    dependencies: BTreeMap<String, String>,
+    #[serde(default)]
+    module: Vec<ModuleSection>,
+    #[serde(default)]
+    command: Vec<CommandSection>,
+    /// Directories to mount, keyed by where they should be mounted.
+    #[serde(default)]
+    fs: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct PackageSection {
+    name: String,
+    version: String,
+    #[serde(default)]
+    description: String,
+    entrypoint: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct ModuleSection {
+    name: String,
+    source: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct CommandSection {
+    name: String,
+    module: String,
+    runner: Option<String>,
+    #[serde(default)]
+    annotations: BTreeMap<String, toml::Value>,
+}
+
+/// The package metadata used by the Wasmer registry.
+#[derive(Debug, Serialize)]
+struct Wapm<'a> {
+    name: &'a str,
+    version: &'a str,
+    description: &'a str,
+}
+
+impl WasmerToml {
+    fn to_manifest(&self, atoms: &BTreeMap<String, Bytes>) -> Result<Manifest, Error> {
+        let mut manifest = Manifest::default();
+        let PackageSection {
+            name,
+            version,
+            description,
+            entrypoint,
+        } = &self.package;
+
+        manifest.package.insert(
+            "wapm".to_string(),
+            cbor(&Wapm {
+                name,
+                version,
+                description,
+            })?,
+        );
+
+        for (dependency, version) in &self.dependencies {
+            manifest.use_map.insert(
+                dependency.clone(),
+                UrlOrManifest::RegistryDependentUrl(format!("{dependency}@{version}")),
+            );
+        }
+
+        for (name, wasm) in atoms {
+            manifest.atoms.insert(
+                name.clone(),
+                Atom {
+                    kind: WASM_ATOM_KIND.parse()?,
+                    signature: signature(wasm),
+                },
+            );
+        }
+
+        for command in &self.command {
+            manifest
+                .commands
+                .insert(command.name.clone(), command.to_webc(atoms)?);
+        }
+
+        manifest.entrypoint = match (entrypoint, self.command.as_slice()) {
+            (Some(entrypoint), _) => Some(entrypoint.clone()),
+            (None, [command]) => Some(command.name.clone()),
+            (None, _) => None,
+        };
+
+        if !self.fs.is_empty() {
+            let mappings = self
+                .fs
+                .iter()
+                .map(|(mount_path, host_path)| FileSystemMapping {
+                    from: None,
+                    volume_name: volume_name(host_path),
+                    host_path: None,
+                    mount_path: mount_path.clone(),
+                })
+                .collect();
+            manifest
+                .package
+                .insert("fs".to_string(), cbor(&FileSystemMappings(mappings))?);
+        }
+
+        Ok(manifest)
+    }
+}
+
+impl CommandSection {
+    fn to_webc(&self, atoms: &BTreeMap<String, Bytes>) -> Result<Command, Error> {
+        anyhow::ensure!(
+            atoms.contains_key(&self.module),
+            "The \"{}\" command refers to an unknown module, \"{}\"",
+            self.name,
+            self.module,
+        );
+
+        let runner = match self.runner.as_deref() {
+            None | Some("wasi") => WASI_RUNNER.to_string(),
+            Some(other) => other.to_string(),
+        };
+
+        let mut annotations = self.annotations.clone();
+        if runner == WASI_RUNNER {
+            // The WASI runner needs to know which atom to run.
+            let wasi = annotations
+                .entry("wasi".to_string())
+                .or_insert_with(|| toml::Value::Table(Default::default()));
+            if let toml::Value::Table(wasi) = wasi {
+                wasi.entry("atom")
+                    .or_insert_with(|| toml::Value::String(self.module.clone()));
+            }
+        }
+
+        let mut command = Command {
+            runner,
+            annotations: Default::default(),
+        };
+        for (key, value) in &annotations {
+            command.annotations.insert(key.clone(), cbor(value)?);
+        }
+
+        Ok(command)
+    }
+}
+
+fn cbor(value: &impl Serialize) -> Result<serde_cbor::Value, Error> {
+    serde_cbor::value::to_value(value).context("Unable to serialize an annotation")
+}
+
+/// The `sha256:<base64>` signature used when describing an atom.
+fn signature(wasm: &[u8]) -> String {
+    let hash = WebcHash::sha256(wasm);
+    format!("sha256:{}", crate::utils::base64_encode(&hash.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn convert_wasmer_toml_to_a_webc_manifest() {
+        let wasmer_toml: WasmerToml = toml::from_str(
+            r#"
+            [package]
+            name = "wasmer-tests/hello"
+            version = "0.1.0"
+
+            [dependencies]
+            "sharrattj/coreutils" = "^1"
+
+            [[module]]
+            name = "hello"
+            source = "hello.wasm"
+
+            [[command]]
+            name = "hello"
+            module = "hello"
+
+            [command.annotations.wasi]
+            env = ["LOG=debug"]
+
+            [fs]
+            "/data" = "./assets"
+            "#,
+        )
+        .unwrap();
+        let atoms = BTreeMap::from([("hello".to_string(), Bytes::from_static(b"\0asm"))]);
+
+        let manifest = wasmer_toml.to_manifest(&atoms).unwrap();
+
+        assert_eq!(manifest.entrypoint.as_deref(), Some("hello"));
+        assert!(manifest.atoms.contains_key("hello"));
+        assert!(manifest.use_map.contains_key("sharrattj/coreutils"));
+        let command = &manifest.commands["hello"];
+        assert_eq!(command.runner, WASI_RUNNER);
+        let wasi: webc::metadata::annotations::Wasi = command.annotation("wasi").unwrap().unwrap();
+        assert_eq!(wasi.atom, "hello");
+        assert_eq!(wasi.env, Some(vec!["LOG=debug".to_string()]));
+        let FileSystemMappings(mappings) = manifest.filesystem().unwrap().unwrap();
+        assert_eq!(mappings[0].volume_name, "/assets");
+        assert_eq!(mappings[0].mount_path, "/data");
+    }
+
+    #[wasm_bindgen_test]
+    fn commands_must_refer_to_known_modules() {
+        let wasmer_toml: WasmerToml = toml::from_str(
+            r#"
+            [package]
+            name = "wasmer-tests/hello"
+            version = "0.1.0"
+
+            [[command]]
+            name = "hello"
+            module = "missing"
+            "#,
+        )
+        .unwrap();
+
+        assert!(wasmer_toml.to_manifest(&BTreeMap::new()).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+//! Caches for compiled WebAssembly modules.
+
+use std::future::Future;
+
+use anyhow::{Context, Error};
+use bytes::Bytes;
+use futures::{channel::oneshot, FutureExt};
+use js_sys::{Promise, Reflect, Uint8Array, WebAssembly};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use wasmer::{Engine, Module};
+use wasmer_wasix::{
+    runtime::module_cache::{CacheError, ModuleCache, ModuleHash, ThreadLocalCache},
+    VirtualTaskManager,
+};
+use web_sys::{DomException, IdbDatabase, IdbObjectStore, IdbRequest, IdbTransactionMode};
+
+use crate::{
+    tasks::{SchedulerMessage, ThreadPool},
+    utils::{js_error, GlobalScope},
+};
+
+/// The default name of the IndexedDB database compiled modules are stored in.
+pub(crate) const DEFAULT_DATABASE_NAME: &str = "wasmer-modules";
+
+const DATABASE_VERSION: u32 = 1;
+const STORE_NAME: &str = "modules";
+const WASM: &str = "wasm";
+const MODULE: &str = "module";
+
+/// A [`ModuleCache`] backed by [IndexedDB][mdn], so compiled modules survive
+/// page reloads.
+///
+/// Each entry holds the original `*.wasm` bytes and, in browsers that can
+/// structured-clone a [`WebAssembly.Module`][WebAssembly::Module], the
+/// compiled module itself.
+///
+/// A [`WebAssembly.Module`][WebAssembly::Module] can only be moved between
+/// threads using `postMessage()`, so modules are kept in a
+/// [`ThreadLocalCache`] and shared with the rest of the [`ThreadPool`] by
+/// sending the scheduler a [`SchedulerMessage::CacheModule`]. If a thread
+/// misses its local cache, it compiles its own copy from the persisted bytes
+/// while any persisted compiled module is handed to the scheduler for the
+/// workers to use.
+///
+/// [mdn]: https://developer.mozilla.org/en-US/docs/Web/API/IndexedDB_API
+#[derive(Debug, Clone)]
+pub(crate) struct IndexedDbCache {
+    pool: ThreadPool,
+    name: String,
+}
+
+impl IndexedDbCache {
+    pub(crate) fn new(pool: ThreadPool, name: impl Into<String>) -> Self {
+        IndexedDbCache {
+            pool,
+            name: name.into(),
+        }
+    }
+
+    /// Run an operation against the underlying database on the thread pool.
+    ///
+    /// Note: Doing this on the current thread could deadlock if it is
+    /// blocked inside a syscall.
+    async fn run<F, Fut, T>(&self, op: F) -> Result<T, Error>
+    where
+        F: FnOnce(IdbDatabase) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Error>> + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let name = self.name.clone();
+
+        self.pool.spawn(Box::new(move || {
+            Box::pin(async move {
+                let _ = sender.send(with_database(&name, op).await);
+            })
+        }))?;
+
+        receiver
+            .await
+            .context("The cache operation was cancelled")?
+    }
+}
+
+#[async_trait::async_trait]
+impl ModuleCache for IndexedDbCache {
+    async fn load(&self, key: ModuleHash, engine: &Engine) -> Result<Module, CacheError> {
+        let memory = ThreadLocalCache::default();
+
+        match memory.load(key, engine).await {
+            Err(CacheError::NotFound) => {}
+            other => return other,
+        }
+
+        let pool = self.pool.clone();
+        let wasm = self
+            .run(move |db| load_entry(db, pool, key))
+            .await
+            .map_err(other)?
+            .ok_or(CacheError::NotFound)?;
+
+        // The scheduler may have already given us a compiled copy.
+        if let Ok(module) = memory.load(key, engine).await {
+            return Ok(module);
+        }
+
+        let module = compile(&wasm).map_err(other)?;
+        memory.save(key, engine, &module).await?;
+
+        Ok(module)
+    }
+
+    async fn save(
+        &self,
+        key: ModuleHash,
+        engine: &Engine,
+        module: &Module,
+    ) -> Result<(), CacheError> {
+        ThreadLocalCache::default()
+            .save(key, engine, module)
+            .await?;
+        self.pool.send(SchedulerMessage::CacheModule {
+            hash: key,
+            module: module.clone(),
+        });
+
+        let wasm = match module.serialize() {
+            Ok(wasm) => wasm,
+            Err(e) => {
+                tracing::debug!(
+                    %key,
+                    error = &e as &dyn std::error::Error,
+                    "Unable to get the module's bytes, so it won't be persisted",
+                );
+                return Ok(());
+            }
+        };
+
+        // Note: The compiled module needs to be transferred to the worker
+        // using postMessage(), so we can't use IndexedDbCache::run() here.
+        let (sender, receiver) = oneshot::channel();
+        let name = self.name.clone();
+        self.pool
+            .spawn_with_module(
+                module.clone(),
+                Box::new(move |module| {
+                    wasm_bindgen_futures::spawn_local(async move {
+                        let result =
+                            with_database(&name, move |db| save_entry(db, key, module, wasm)).await;
+                        let _ = sender.send(result);
+                    });
+                }),
+            )
+            .map_err(|e| other(e.into()))?;
+
+        receiver
+            .await
+            .context("The cache operation was cancelled")
+            .and_then(|result| result)
+            .map_err(other)
+    }
+}
+
+fn other(error: Error) -> CacheError {
+    CacheError::Other(error.into())
+}
+
+/// Compile a `*.wasm` file on the current thread.
+pub(crate) fn compile(wasm: &[u8]) -> Result<Module, Error> {
+    let wasm = unsafe { Uint8Array::view(wasm) };
+    let module = WebAssembly::Module::new(&wasm).map_err(js_error)?;
+    // Note: We need to use this From impl because it will use the
+    // wasm-types-polyfill to parse the *.wasm file's import section.
+    //
+    // The browser doesn't give you any way to inspect the imports at the
+    // moment, so without the polyfill we'll always assume the module wants
+    // a minimum of 1 page of memory. This causes modules that want more
+    // memory by default (e.g. sharrattj/bash) to fail with an instantiation
+    // error.
+    //
+    // https://github.com/wasmerio/wasmer/blob/8ec4f1d76062e2a612ac2f70f4a73eaf59f8fe9f/lib/api/src/js/module.rs#L323-L328
+    Ok(Module::from((module, wasm.to_vec())))
+}
+
+/// Save a module to the current thread's [`ThreadLocalCache`].
+pub(crate) fn cache_locally(hash: ModuleHash, module: &Module) {
+    // Note: The ThreadLocalCache never suspends, so this will always complete
+    // immediately.
+    let _ = ThreadLocalCache::default()
+        .save(hash, &Engine::default(), module)
+        .now_or_never();
+}
+
+async fn with_database<F, Fut, T>(name: &str, op: F) -> Result<T, Error>
+where
+    F: FnOnce(IdbDatabase) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let db = open_database(name).await?;
+    let result = op(db.clone()).await;
+    db.close();
+    result
+}
+
+async fn open_database(name: &str) -> Result<IdbDatabase, Error> {
+    let factory = GlobalScope::current()
+        .indexed_db()
+        .context("IndexedDB isn't available")?;
+    let open = factory
+        .open_with_u32(name, DATABASE_VERSION)
+        .map_err(js_error)?;
+
+    let on_upgrade_needed = Closure::<dyn FnMut(JsValue)>::new({
+        let open = open.clone();
+        move |_| {
+            if let Ok(db) = open.result() {
+                let db: IdbDatabase = db.unchecked_into();
+                if let Err(e) = db.create_object_store(STORE_NAME) {
+                    tracing::warn!(error = &*js_error(e), "Unable to create the object store");
+                }
+            }
+        }
+    });
+    open.set_onupgradeneeded(Some(on_upgrade_needed.as_ref().unchecked_ref()));
+
+    let db = request(&open)
+        .await
+        .with_context(|| format!("Unable to open the \"{name}\" database"));
+    open.set_onupgradeneeded(None);
+
+    Ok(db?.unchecked_into())
+}
+
+fn object_store(db: &IdbDatabase, mode: IdbTransactionMode) -> Result<IdbObjectStore, Error> {
+    db.transaction_with_str_and_mode(STORE_NAME, mode)
+        .and_then(|tx| tx.object_store(STORE_NAME))
+        .map_err(js_error)
+}
+
+/// Wait for an [`IdbRequest`] to complete, returning its result.
+async fn request(request: &IdbRequest) -> Result<JsValue, Error> {
+    let mut on_success = None;
+    let mut on_error = None;
+    let promise = Promise::new(&mut |resolve, reject| {
+        on_success = Some(Closure::<dyn FnMut(JsValue)>::once(move |_: JsValue| {
+            let _ = resolve.call0(&JsValue::NULL);
+        }));
+        on_error = Some(Closure::<dyn FnMut(JsValue)>::once(move |_: JsValue| {
+            let _ = reject.call0(&JsValue::NULL);
+        }));
+    });
+
+    request.set_onsuccess(on_success.as_ref().map(|f| f.as_ref().unchecked_ref()));
+    request.set_onerror(on_error.as_ref().map(|f| f.as_ref().unchecked_ref()));
+    let outcome = JsFuture::from(promise).await;
+    request.set_onsuccess(None);
+    request.set_onerror(None);
+
+    match outcome {
+        Ok(_) => request.result().map_err(js_error),
+        Err(_) => match request.error() {
+            Ok(Some(error)) => Err(js_error(error.into())),
+            Ok(None) => Err(Error::msg("The IndexedDB request failed")),
+            Err(e) => Err(js_error(e)),
+        },
+    }
+}
+
+async fn load_entry(
+    db: IdbDatabase,
+    pool: ThreadPool,
+    key: ModuleHash,
+) -> Result<Option<Bytes>, Error> {
+    let store = object_store(&db, IdbTransactionMode::Readonly)?;
+    let get = store
+        .get(&JsValue::from(key.to_string()))
+        .map_err(js_error)?;
+    let entry = request(&get).await?;
+    if entry.is_undefined() {
+        return Ok(None);
+    }
+
+    let wasm = Reflect::get(&entry, &JsValue::from_str(WASM))
+        .map_err(js_error)?
+        .dyn_into::<Uint8Array>()
+        .map_err(|_| Error::msg("The cached module is missing its bytes"))?
+        .to_vec();
+
+    let module = Reflect::get(&entry, &JsValue::from_str(MODULE)).map_err(js_error)?;
+    if let Ok(module) = module.dyn_into::<WebAssembly::Module>() {
+        // Let the scheduler share the compiled module with every thread that
+        // doesn't have it yet.
+        pool.send(SchedulerMessage::CacheModule {
+            hash: key,
+            module: Module::from((module, wasm.clone())),
+        });
+    }
+
+    Ok(Some(wasm.into()))
+}
+
+async fn save_entry(
+    db: IdbDatabase,
+    key: ModuleHash,
+    module: Module,
+    wasm: Bytes,
+) -> Result<(), Error> {
+    let entry = js_sys::Object::new();
+    Reflect::set(
+        &entry,
+        &JsValue::from_str(WASM),
+        &Uint8Array::from(&wasm[..]),
+    )
+    .map_err(js_error)?;
+    Reflect::set(&entry, &JsValue::from_str(MODULE), &JsValue::from(module)).map_err(js_error)?;
+    let key = JsValue::from(key.to_string());
+
+    let put = match object_store(&db, IdbTransactionMode::Readwrite)?.put_with_key(&entry, &key) {
+        Ok(put) => put,
+        Err(e) if is_data_clone_error(&e) => {
+            // Most browsers can't structured-clone a WebAssembly.Module, so
+            // fall back to storing the bytes by themselves.
+            tracing::debug!("Unable to store the compiled module, storing its bytes instead");
+            Reflect::delete_property(&entry, &JsValue::from_str(MODULE)).map_err(js_error)?;
+            object_store(&db, IdbTransactionMode::Readwrite)?
+                .put_with_key(&entry, &key)
+                .map_err(js_error)?
+        }
+        Err(e) => return Err(js_error(e)),
+    };
+    request(&put).await.context("Unable to save the module")?;
+
+    Ok(())
+}
+
+fn is_data_clone_error(error: &JsValue) -> bool {
+    error
+        .dyn_ref::<DomException>()
+        .map(|e| e.name() == "DataCloneError")
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    const ENVVAR: &[u8] = include_bytes!("../tests/envvar.wasm");
+
+    #[wasm_bindgen_test]
+    async fn persisted_modules_can_be_loaded_again() {
+        let name = format!("wasmer-test-{}", js_sys::Math::random());
+        let cache = IndexedDbCache::new(ThreadPool::new(), name);
+        let key = ModuleHash::hash(ENVVAR);
+        let module = compile(ENVVAR).unwrap();
+
+        cache.save(key, &Engine::default(), &module).await.unwrap();
+
+        let pool = cache.pool.clone();
+        let wasm = cache
+            .run(move |db| load_entry(db, pool, key))
+            .await
+            .unwrap();
+        assert_eq!(wasm.as_deref(), Some(ENVVAR));
+    }
+
+    #[wasm_bindgen_test]
+    async fn unknown_modules_are_not_found() {
+        let name = format!("wasmer-test-{}", js_sys::Math::random());
+        let cache = IndexedDbCache::new(ThreadPool::new(), name);
+
+        let err = cache
+            .load(ModuleHash::hash(b"not a module"), &Engine::default())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, CacheError::NotFound));
+    }
+}
@@ -1,12 +1,18 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::Context;
-use js_sys::Array;
-use virtual_fs::{FileSystem, TmpFileSystem};
+use js_sys::{Array, Reflect, Uint8Array};
+use virtual_fs::{FileSystem, TmpFileSystem, VirtualFile};
 use wasm_bindgen::{prelude::wasm_bindgen, JsCast, JsValue, UnwrapThrowExt};
 use wasmer_wasix::WasiEnvBuilder;
 
-use crate::{runtime::Runtime, utils::Error, Directory, DirectoryInit, JsRuntime, StringOrBytes};
+use crate::{
+    fs::ReadOnlyFileSystem,
+    runtime::Runtime,
+    tty::{TtySettings, TtyState},
+    utils::Error,
+    Directory, DirectoryInit, JsRuntime, StringOrBytes,
+};
 
 #[wasm_bindgen]
 extern "C" {
@@ -16,6 +22,39 @@ extern "C" {
 
     #[wasm_bindgen]
     type OptionalDirectories;
+
+    #[wasm_bindgen(typescript_type = "OutputFile")]
+    type OutputFile;
+
+    #[wasm_bindgen(method, getter)]
+    fn directory(this: &OutputFile) -> JsValue;
+
+    #[wasm_bindgen(method, getter)]
+    fn file(this: &OutputFile) -> Option<String>;
+
+    #[wasm_bindgen(method, getter)]
+    fn append(this: &OutputFile) -> Option<bool>;
+
+    #[wasm_bindgen(typescript_type = "MountOptions")]
+    type MountOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn readonly(this: &MountOptions) -> Option<bool>;
+
+    #[wasm_bindgen(typescript_type = "TtyOptions")]
+    type JsTtyOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn cols(this: &JsTtyOptions) -> Option<u32>;
+
+    #[wasm_bindgen(method, getter)]
+    fn rows(this: &JsTtyOptions) -> Option<u32>;
+
+    #[wasm_bindgen(method, getter)]
+    fn echo(this: &JsTtyOptions) -> Option<bool>;
+
+    #[wasm_bindgen(method, getter, js_name = "lineBuffered")]
+    fn line_buffered(this: &JsTtyOptions) -> Option<bool>;
 }
 
 #[wasm_bindgen(typescript_custom_section)]
@@ -28,19 +67,166 @@ type CommonOptions = {
     args?: string[];
     /** Environment variables to set. */
     env?: Record<string, string>;
-    /** The standard input stream. */
-    stdin?: string | Uint8Array;
+    /**
+     * The standard input stream.
+     *
+     * Streams, blobs and async iterables are piped into the program as it
+     * reads them, so the entire input never needs to be held in memory.
+     * Otherwise, you can write to {@link Instance.stdin} once the program has
+     * started.
+     */
+    stdin?:
+        | string
+        | Uint8Array
+        | ReadableStream<Uint8Array>
+        | Blob
+        | AsyncIterable<string | ArrayBuffer | ArrayBufferView>;
+    /**
+     * Write the program's stdout directly to a file instead of
+     * {@link Instance.stdout}.
+     */
+    stdout?: OutputFile;
+    /**
+     * Write the program's stderr directly to a file instead of
+     * {@link Instance.stderr}.
+     */
+    stderr?: OutputFile;
+    /**
+     * Combine stdout and stderr into a single {@link Instance.output} stream
+     * of {@link OutputChunk}s, preserving the order they were written in.
+     *
+     * When set, {@link Instance.stdout} and {@link Instance.stderr} will be
+     * empty.
+     */
+    mergeStderr?: boolean;
+    /**
+     * Called with each chunk the program writes to stdout.
+     *
+     * If the callback returns a promise, the next chunk won't be delivered
+     * until it resolves. {@link Instance.stdout} will be locked and the
+     * {@link Output} from {@link Instance.wait} won't include stdout.
+     */
+    onStdout?: (chunk: Uint8Array) => void | Promise<void>;
+    /**
+     * Called with each chunk the program writes to stderr.
+     *
+     * This behaves the same as {@link CommonOptions.onStdout}. When
+     * {@link CommonOptions.mergeStderr} is set, chunks are delivered in the
+     * order they were written and any chunks without a callback are
+     * discarded.
+     */
+    onStderr?: (chunk: Uint8Array) => void | Promise<void>;
     /**
      * Directories that should be mounted inside the WASIX instance.
      *
      * This maps mount locations to the {@link Directory} being mounted. As a
      * shortcut, if {@link DirectoryInit} is provided, a new {@link Directory}
-     * will be instantiated and mounted.
+     * will be instantiated and mounted. Use a {@link MountOptions} object to
+     * control how the directory is mounted (e.g. to make it read-only).
      *
      * Avoid mounting directly to `"/"` as it may clobber a package's bundled
      * files.
      */
-    mount?: Record<string, DirectoryInit | Directory>;
+    mount?: Record<string, DirectoryInit | Directory | MountOptions>;
+    /**
+     * The maximum amount of time (in milliseconds) the program is allowed to
+     * run for.
+     *
+     * If the program is still running when the timeout elapses, it will be
+     * killed and {@link Instance.wait} will reject with a
+     * {@link TimeoutError}.
+     */
+    timeout?: number;
+    /**
+     * The program's initial working directory.
+     *
+     * Defaults to `"/"`. Unless {@link CommonOptions.createCwd} is set, the
+     * directory must already exist.
+     */
+    cwd?: string;
+    /**
+     * Create {@link CommonOptions.cwd} (and any missing parent directories)
+     * if it doesn't already exist.
+     *
+     * When using {@link Command.run}, the directory must be inside one of the
+     * mounted directories.
+     */
+    createCwd?: boolean;
+    /**
+     * Should the program be connected to a terminal?
+     *
+     * When connected, the program will see stdin, stdout, and stderr as a
+     * TTY and any input will be passed through a line discipline (echo, line
+     * buffering, etc.) before the program reads it. Pass {@link TtyOptions}
+     * to configure the terminal.
+     *
+     * Defaults to connecting a terminal when using {@link Command.run} without
+     * {@link CommonOptions.stdin}, and never connecting one when using
+     * {@link runWasix}.
+     */
+    tty?: boolean | TtyOptions;
+};
+
+/**
+ * Settings for the terminal a program is connected to.
+ */
+export type TtyOptions = {
+    /** The number of columns. */
+    cols?: number;
+    /** The number of rows. */
+    rows?: number;
+    /** Echo input back to stdout. Defaults to `true`. */
+    echo?: boolean;
+    /**
+     * Only pass input to the program once a full line has been entered.
+     * Defaults to `true`.
+     */
+    lineBuffered?: boolean;
+};
+
+/**
+ * A chunk of output read from {@link Instance.output}.
+ */
+export type OutputChunk = {
+    /** Which stream the program wrote this data to. */
+    stream: "stdout" | "stderr";
+    data: Uint8Array;
+};
+
+/**
+ * A file that a program's output can be written to.
+ *
+ * The corresponding stream on {@link Instance} will be empty.
+ */
+export type OutputFile = {
+    /** The {@link Directory} containing the file. */
+    directory: Directory;
+    /**
+     * The file's path within the directory.
+     *
+     * The file and any missing parent directories will be created if they
+     * don't already exist.
+     */
+    file: string;
+    /**
+     * Add to the end of the file instead of overwriting it.
+     */
+    append?: boolean;
+};
+
+/**
+ * Options for a single entry in {@link CommonOptions.mount}.
+ */
+export type MountOptions = {
+    /** The directory being mounted. */
+    directory: DirectoryInit | Directory;
+    /**
+     * Prevent the program from modifying the directory.
+     *
+     * Any attempt to create, modify, rename, or delete files will fail with
+     * `EACCES`.
+     */
+    readonly?: boolean;
 };
 
 /**
@@ -83,10 +269,37 @@ extern "C" {
     fn env(this: &CommonOptions) -> JsValue;
 
     #[wasm_bindgen(method, getter)]
-    fn stdin(this: &CommonOptions) -> Option<StringOrBytes>;
+    fn stdin(this: &CommonOptions) -> JsValue;
 
     #[wasm_bindgen(method, getter)]
     fn mount(this: &CommonOptions) -> OptionalDirectories;
+
+    #[wasm_bindgen(method, getter)]
+    fn timeout(this: &CommonOptions) -> Option<f64>;
+
+    #[wasm_bindgen(method, getter)]
+    fn stdout(this: &CommonOptions) -> Option<OutputFile>;
+
+    #[wasm_bindgen(method, getter)]
+    fn stderr(this: &CommonOptions) -> Option<OutputFile>;
+
+    #[wasm_bindgen(method, getter, js_name = "mergeStderr")]
+    fn merge_stderr(this: &CommonOptions) -> Option<bool>;
+
+    #[wasm_bindgen(method, getter)]
+    fn cwd(this: &CommonOptions) -> Option<String>;
+
+    #[wasm_bindgen(method, getter, js_name = "createCwd")]
+    pub(crate) fn create_cwd(this: &CommonOptions) -> Option<bool>;
+
+    #[wasm_bindgen(method, getter)]
+    fn tty(this: &CommonOptions) -> JsValue;
+
+    #[wasm_bindgen(method, getter, js_name = "onStdout")]
+    pub(crate) fn on_stdout(this: &CommonOptions) -> Option<js_sys::Function>;
+
+    #[wasm_bindgen(method, getter, js_name = "onStderr")]
+    pub(crate) fn on_stderr(this: &CommonOptions) -> Option<js_sys::Function>;
 }
 
 impl CommonOptions {
@@ -107,8 +320,121 @@ impl CommonOptions {
         }
     }
 
-    pub(crate) fn read_stdin(&self) -> Option<Vec<u8>> {
-        self.stdin().map(|s| s.as_bytes())
+    pub(crate) fn parse_stdin(&self) -> Result<Option<Stdin>, Error> {
+        let stdin = self.stdin();
+
+        if stdin.is_undefined() || stdin.is_null() {
+            Ok(None)
+        } else if stdin.is_string() || stdin.is_instance_of::<Uint8Array>() {
+            let stdin: &StringOrBytes = stdin.unchecked_ref();
+            Ok(Some(Stdin::Bytes(stdin.as_bytes())))
+        } else if let Some(stream) = stdin.dyn_ref::<web_sys::ReadableStream>() {
+            Ok(Some(Stdin::Stream(stream.clone())))
+        } else if let Some(blob) = stdin.dyn_ref::<web_sys::Blob>() {
+            Ok(Some(Stdin::Stream(blob.stream())))
+        } else if let Some(iterator) = async_iterator(&stdin)? {
+            let stream = crate::streams::async_iterator_stream(iterator);
+            Ok(Some(Stdin::Stream(stream)))
+        } else {
+            Err(Error::js(js_sys::TypeError::new(
+                "stdin should be a string, Uint8Array, ReadableStream, Blob, or async iterable",
+            )))
+        }
+    }
+
+    pub(crate) fn parse_timeout(&self) -> Result<Option<Duration>, Error> {
+        match self.timeout() {
+            Some(ms) if ms.is_finite() && ms >= 0.0 => {
+                Ok(Some(Duration::from_secs_f64(ms / 1000.0)))
+            }
+            Some(_) => Err(Error::js(js_sys::RangeError::new(
+                "The timeout must be a non-negative number of milliseconds",
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Work out where the program's stdout and stderr should be written to,
+    /// and the streams JavaScript can read them from.
+    pub(crate) fn output_targets(&self) -> Result<OutputTargets, Error> {
+        let stdout_file = self
+            .stdout()
+            .map(|f| open_output_file(f, "stdout"))
+            .transpose()?;
+        let stderr_file = self
+            .stderr()
+            .map(|f| open_output_file(f, "stderr"))
+            .transpose()?;
+
+        if self.merge_stderr().unwrap_or(false) {
+            let (stdout_pipe, stderr_pipe, output) = crate::streams::merged_output_pipe();
+
+            return Ok(OutputTargets {
+                stdout: stdout_file.unwrap_or_else(|| Box::new(stdout_pipe)),
+                stdout_stream: crate::streams::closed_output_stream(),
+                stderr: stderr_file.unwrap_or_else(|| Box::new(stderr_pipe)),
+                stderr_stream: crate::streams::closed_output_stream(),
+                output: Some(output),
+            });
+        }
+
+        let (stdout, stdout_stream) = match stdout_file {
+            Some(f) => (f, crate::streams::closed_output_stream()),
+            None => {
+                let (pipe, stream) = crate::streams::output_pipe();
+                (Box::new(pipe) as Box<dyn VirtualFile + Send + Sync>, stream)
+            }
+        };
+        let (stderr, stderr_stream) = match stderr_file {
+            Some(f) => (f, crate::streams::closed_output_stream()),
+            None => {
+                let (pipe, stream) = crate::streams::output_pipe();
+                (Box::new(pipe) as Box<dyn VirtualFile + Send + Sync>, stream)
+            }
+        };
+
+        Ok(OutputTargets {
+            stdout,
+            stdout_stream,
+            stderr,
+            stderr_stream,
+            output: None,
+        })
+    }
+
+    pub(crate) fn parse_tty(&self) -> Result<TtyMode, Error> {
+        let tty = self.tty();
+
+        if tty.is_undefined() || tty.is_null() {
+            Ok(TtyMode::Inferred)
+        } else if let Some(enabled) = tty.as_bool() {
+            if enabled {
+                Ok(TtyMode::Enabled(TtySettings::default()))
+            } else {
+                Ok(TtyMode::Disabled)
+            }
+        } else if tty.is_object() {
+            let options: &JsTtyOptions = tty.unchecked_ref();
+            Ok(TtyMode::Enabled(TtySettings {
+                cols: options.cols(),
+                rows: options.rows(),
+                echo: options.echo(),
+                line_buffered: options.line_buffered(),
+            }))
+        } else {
+            Err(Error::js(js_sys::TypeError::new(
+                "tty should be a boolean or TtyOptions",
+            )))
+        }
+    }
+
+    pub(crate) fn parse_cwd(&self) -> Option<PathBuf> {
+        let mut cwd = self.cwd()?;
+        if !cwd.starts_with('/') {
+            cwd.insert(0, '/');
+        }
+
+        Some(PathBuf::from(cwd))
     }
 
     pub(crate) fn mounted_directories(
@@ -124,27 +450,137 @@ impl CommonOptions {
         for (key, value) in &entries {
             let key = String::from(key.clone());
 
-            // Note: the value is a `Directory | DirectoryInit`
+            // Note: the value is a `Directory | DirectoryInit | MountOptions`.
+            // A DirectoryInit only ever contains strings and byte arrays, so
+            // a "directory" field holding an object means we've been given
+            // MountOptions.
+            let (dir, readonly) = match Reflect::get(value, &"directory".into()) {
+                Ok(dir) if dir.is_object() && !dir.is_instance_of::<Uint8Array>() => {
+                    let opts: &MountOptions = value.unchecked_ref();
+                    (dir, opts.readonly().unwrap_or(false))
+                }
+                _ => (value.clone(), false),
+            };
 
-            let value: Arc<dyn FileSystem + Send + Sync> = {
-                if let Ok(dir) = Directory::try_from(value) {
+            let mut fs: Arc<dyn FileSystem + Send + Sync> = {
+                if let Ok(dir) = Directory::try_from(&dir) {
                     Arc::new(dir)
-                } else if value.is_object() {
+                } else if dir.is_object() {
                     // looks like we were given parameters for initializing a
                     // Directory and need to call the constructor ourselves
-                    let init: &DirectoryInit = value.unchecked_ref();
+                    let init: &DirectoryInit = dir.unchecked_ref();
                     Arc::new(Directory::new(Some(init.clone()))?)
                 } else {
                     unreachable!();
                 }
             };
-            mounted_directories.push((key, value));
+
+            if readonly {
+                fs = Arc::new(ReadOnlyFileSystem::new(fs));
+            }
+
+            mounted_directories.push((key, fs));
         }
 
         Ok(mounted_directories)
     }
 }
 
+/// Whether a program should be connected to a terminal.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TtyMode {
+    /// Nothing was specified, so it's up to the caller to decide.
+    Inferred,
+    Disabled,
+    Enabled(TtySettings),
+}
+
+/// Where a program's stdin comes from.
+#[derive(Debug)]
+pub(crate) enum Stdin {
+    /// Input that is known up front.
+    Bytes(Vec<u8>),
+    /// Input that will be piped into the program as it is read.
+    Stream(web_sys::ReadableStream),
+}
+
+impl Stdin {
+    /// Turn this into a file that can be used as the program's stdin.
+    pub(crate) fn into_file(self) -> Box<dyn VirtualFile + Send + Sync> {
+        match self {
+            Stdin::Bytes(bytes) => Box::new(virtual_fs::StaticFile::new(bytes)),
+            Stdin::Stream(stream) => Box::new(crate::streams::readable_stream_pipe(stream)),
+        }
+    }
+}
+
+/// Where a program's output will be written to.
+#[derive(Debug)]
+pub(crate) struct OutputTargets {
+    /// The file to use as the program's stdout.
+    pub stdout: Box<dyn VirtualFile + Send + Sync>,
+    /// The stream JavaScript can read stdout from.
+    pub stdout_stream: web_sys::ReadableStream,
+    /// The file to use as the program's stderr.
+    pub stderr: Box<dyn VirtualFile + Send + Sync>,
+    /// The stream JavaScript can read stderr from.
+    pub stderr_stream: web_sys::ReadableStream,
+    /// Interleaved stdout and stderr, if they were merged.
+    pub output: Option<web_sys::ReadableStream>,
+}
+
+fn open_output_file(
+    output: OutputFile,
+    name: &str,
+) -> Result<Box<dyn VirtualFile + Send + Sync>, Error> {
+    let directory = Directory::try_from(&output.directory()).map_err(|_| {
+        Error::js(js_sys::TypeError::new(&format!(
+            "{name}.directory should be a Directory"
+        )))
+    })?;
+    let Some(mut path) = output.file() else {
+        return Err(Error::js(js_sys::TypeError::new(&format!(
+            "{name}.file should be a path"
+        ))));
+    };
+    if !path.starts_with('/') {
+        path.insert(0, '/');
+    }
+    let path = PathBuf::from(path);
+
+    if let Some(parent) = path.parent() {
+        crate::fs::create_dir_all(&directory, parent)?;
+    }
+
+    let append = output.append().unwrap_or(false);
+    let f = directory
+        .new_open_options()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(&path)
+        .with_context(|| format!("Unable to open \"{}\" for {name}", path.display()))?;
+
+    Ok(f)
+}
+
+/// Get an iterator from an object implementing the async iterable protocol.
+fn async_iterator(value: &JsValue) -> Result<Option<js_sys::AsyncIterator>, Error> {
+    if !value.is_object() {
+        return Ok(None);
+    }
+
+    let method = Reflect::get(value, &js_sys::Symbol::async_iterator()).map_err(Error::js)?;
+    match method.dyn_ref::<js_sys::Function>() {
+        Some(method) => {
+            let iterator = method.call0(value).map_err(Error::js)?;
+            Ok(Some(iterator.unchecked_into()))
+        }
+        None => Ok(None),
+    }
+}
+
 impl Default for CommonOptions {
     fn default() -> Self {
         // Note: all fields are optional, so it's fine to use an empty object.
@@ -170,15 +606,18 @@ extern "C" {
 
 impl RunOptions {
     /// Propagate any provided options to the [`WasiEnvBuilder`], returning
-    /// streams that can be used for stdin/stdout/stderr.
+    /// streams that can be used for stdin/stdout/stderr and the merged
+    /// output.
     pub(crate) fn configure_builder(
         &self,
         builder: &mut WasiEnvBuilder,
+        tty: &TtyState,
     ) -> Result<
         (
             Option<web_sys::WritableStream>,
             web_sys::ReadableStream,
             web_sys::ReadableStream,
+            Option<web_sys::ReadableStream>,
         ),
         Error,
     > {
@@ -190,30 +629,42 @@ impl RunOptions {
             builder.add_env(key, value);
         }
 
-        let stdin = match self.read_stdin() {
-            Some(stdin) => {
-                let f = virtual_fs::StaticFile::new(stdin);
-                builder.set_stdin(Box::new(f));
-                None
-            }
-            None => {
-                let (f, stdin) = crate::streams::input_pipe();
-                builder.set_stdin(Box::new(f));
-                Some(stdin)
+        let interactive = match self.parse_tty()? {
+            TtyMode::Inferred | TtyMode::Disabled => false,
+            TtyMode::Enabled(settings) => {
+                settings.apply(tty);
+                true
             }
         };
 
-        let (stdout_file, stdout) = crate::streams::output_pipe();
-        builder.set_stdout(Box::new(stdout_file));
-
-        let (stderr_file, stderr) = crate::streams::output_pipe();
-        builder.set_stderr(Box::new(stderr_file));
+        let outputs = self.output_targets()?;
+        let terminal = crate::tty::connect(tty, interactive, self.parse_stdin()?, outputs.stdout);
+        builder.set_stdin(terminal.stdin);
+        builder.set_stdout(terminal.stdout);
+        builder.set_stderr(outputs.stderr);
 
         let fs = self.filesystem()?;
+
+        if let Some(cwd) = self.parse_cwd() {
+            let create = self.create_cwd().unwrap_or(false);
+            crate::fs::ensure_dir(&fs, &cwd, create).with_context(|| {
+                format!(
+                    "Unable to use \"{}\" as the working directory",
+                    cwd.display()
+                )
+            })?;
+            builder.set_current_dir(cwd);
+        }
+
         builder.set_fs(Box::new(fs));
         builder.add_preopen_dir("/")?;
 
-        Ok((stdin, stdout, stderr))
+        Ok((
+            terminal.stdin_stream,
+            outputs.stdout_stream,
+            outputs.stderr_stream,
+            outputs.output,
+        ))
     }
 
     pub(crate) fn filesystem(&self) -> Result<TmpFileSystem, Error> {
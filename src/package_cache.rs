@@ -0,0 +1,394 @@
+//! Caches for downloaded `*.webc` files.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    future::Future,
+    sync::Mutex,
+};
+
+use anyhow::{Context, Error};
+use bytes::Bytes;
+use futures::channel::oneshot;
+use js_sys::Uint8Array;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use wasmer_wasix::runtime::resolver::WebcHash;
+use web_sys::{Cache, Response};
+
+use crate::{
+    package_loader::IntegrityError,
+    tasks::ThreadPool,
+    utils::{js_error, GlobalScope},
+};
+
+/// The default name used when storing packages in Cache Storage.
+pub(crate) const DEFAULT_CACHE_NAME: &str = "wasmer-packages";
+/// The default maximum number of bytes a persistent cache will hold.
+pub(crate) const DEFAULT_MAX_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Somewhere downloaded packages can be stored, keyed by their [`WebcHash`].
+#[async_trait::async_trait]
+pub(crate) trait PackageCache: Debug + Send + Sync {
+    /// Look up a previously saved package.
+    ///
+    /// Packages are checked against their hash before being returned. Any
+    /// that have been corrupted are removed and treated as a cache miss.
+    async fn load(&self, hash: &WebcHash) -> Result<Option<Bytes>, Error>;
+    /// Save a package so it can be loaded later.
+    ///
+    /// This will fail with an [`IntegrityError`] if the package doesn't have
+    /// the provided hash.
+    async fn save(&self, hash: WebcHash, bytes: Bytes) -> Result<(), Error>;
+    /// Remove a package from the cache (e.g. because it was corrupted).
+    async fn remove(&self, hash: &WebcHash) -> Result<(), Error>;
+}
+
+/// A quick'n'dirty cache which keeps packages in memory for as long as the
+/// [`crate::package_loader::PackageLoader`] is alive.
+#[derive(Debug, Default)]
+pub(crate) struct MemoryCache(Mutex<HashMap<WebcHash, Bytes>>);
+
+#[async_trait::async_trait]
+impl PackageCache for MemoryCache {
+    async fn load(&self, hash: &WebcHash) -> Result<Option<Bytes>, Error> {
+        let cache = self.0.lock().unwrap();
+        Ok(cache.get(hash).cloned())
+    }
+
+    async fn save(&self, hash: WebcHash, bytes: Bytes) -> Result<(), Error> {
+        IntegrityError::check(hash, &bytes)?;
+
+        self.0.lock().unwrap().insert(hash, bytes);
+        Ok(())
+    }
+
+    async fn remove(&self, hash: &WebcHash) -> Result<(), Error> {
+        self.0.lock().unwrap().remove(hash);
+        Ok(())
+    }
+}
+
+/// A [`PackageCache`] backed by the browser's [Cache Storage][mdn], so
+/// packages survive page reloads.
+///
+/// Entries are checked against their [`WebcHash`] when loaded, and the least
+/// recently used packages are evicted once the cache grows beyond
+/// `max_size` bytes.
+///
+/// Cache Storage can only be accessed from a thread with an event loop, so
+/// every operation is run as a task on the [`ThreadPool`].
+///
+/// [mdn]: https://developer.mozilla.org/en-US/docs/Web/API/CacheStorage
+#[derive(Debug, Clone)]
+pub(crate) struct CacheStorageCache {
+    pool: ThreadPool,
+    name: String,
+    max_size: u64,
+}
+
+impl CacheStorageCache {
+    pub(crate) fn new(pool: ThreadPool, name: impl Into<String>, max_size: u64) -> Self {
+        CacheStorageCache {
+            pool,
+            name: name.into(),
+            max_size,
+        }
+    }
+
+    /// Run an operation against the underlying [`Cache`] on the thread pool.
+    async fn run<F, Fut, T>(&self, op: F) -> Result<T, Error>
+    where
+        F: FnOnce(Cache, u64) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Error>> + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let name = self.name.clone();
+        let max_size = self.max_size;
+
+        self.pool.spawn(Box::new(move || {
+            Box::pin(async move {
+                let result = match open_cache(&name).await {
+                    Ok(cache) => op(cache, max_size).await,
+                    Err(e) => Err(e),
+                };
+                let _ = sender.send(result);
+            })
+        }))?;
+
+        receiver
+            .await
+            .context("The cache operation was cancelled")?
+    }
+}
+
+#[async_trait::async_trait]
+impl PackageCache for CacheStorageCache {
+    async fn load(&self, hash: &WebcHash) -> Result<Option<Bytes>, Error> {
+        let hash = *hash;
+        self.run(move |cache, _| load_entry(cache, hash)).await
+    }
+
+    async fn save(&self, hash: WebcHash, bytes: Bytes) -> Result<(), Error> {
+        IntegrityError::check(hash, &bytes)?;
+
+        if bytes.len() as u64 > self.max_size {
+            tracing::debug!(
+                %hash,
+                size = bytes.len(),
+                max_size = self.max_size,
+                "The package is too big to be cached",
+            );
+            return Ok(());
+        }
+
+        self.run(move |cache, max_size| save_entry(cache, max_size, hash, bytes))
+            .await
+    }
+
+    async fn remove(&self, hash: &WebcHash) -> Result<(), Error> {
+        let hash = *hash;
+        self.run(move |cache, _| remove_entry(cache, hash)).await
+    }
+}
+
+const ENTRY_URL_PREFIX: &str = "https://wasmer.invalid/webc/";
+const INDEX_URL: &str = "https://wasmer.invalid/index.json";
+
+fn entry_url(hash: &WebcHash) -> String {
+    format!("{ENTRY_URL_PREFIX}{hash}")
+}
+
+async fn open_cache(name: &str) -> Result<Cache, Error> {
+    let caches = GlobalScope::current()
+        .caches()
+        .context("Cache Storage isn't available")?;
+    let cache = JsFuture::from(caches.open(name))
+        .await
+        .map_err(js_error)
+        .with_context(|| format!("Unable to open the \"{name}\" cache"))?;
+
+    Ok(cache.unchecked_into())
+}
+
+async fn load_entry(cache: Cache, hash: WebcHash) -> Result<Option<Bytes>, Error> {
+    let url = entry_url(&hash);
+
+    let response = JsFuture::from(cache.match_with_str(&url))
+        .await
+        .map_err(js_error)?;
+    if response.is_undefined() {
+        return Ok(None);
+    }
+    let response: Response = response.unchecked_into();
+    let body = response.array_buffer().map_err(js_error)?;
+    let body = JsFuture::from(body).await.map_err(js_error)?;
+    let bytes = Uint8Array::new(&body).to_vec();
+
+    if let Err(e) = IntegrityError::check(hash, &bytes) {
+        tracing::warn!(
+            error = &e as &dyn std::error::Error,
+            "Evicting a corrupted package from the cache",
+        );
+        remove_entry(cache, hash).await?;
+        return Ok(None);
+    }
+
+    let mut index = Index::load(&cache).await?;
+    index.touch(&hash, bytes.len() as u64, js_sys::Date::now());
+    index.save(&cache).await?;
+
+    Ok(Some(bytes.into()))
+}
+
+async fn save_entry(
+    cache: Cache,
+    max_size: u64,
+    hash: WebcHash,
+    bytes: Bytes,
+) -> Result<(), Error> {
+    let response = Response::new_with_opt_u8_array(Some(&mut bytes.to_vec())).map_err(js_error)?;
+    JsFuture::from(cache.put_with_str(&entry_url(&hash), &response))
+        .await
+        .map_err(js_error)
+        .context("Unable to save the package")?;
+
+    let mut index = Index::load(&cache).await?;
+    index.touch(&hash, bytes.len() as u64, js_sys::Date::now());
+
+    for evicted in index.evict(max_size) {
+        tracing::debug!(hash = %evicted, "Evicting a package from the cache");
+        JsFuture::from(cache.delete_with_str(&format!("{ENTRY_URL_PREFIX}{evicted}")))
+            .await
+            .map_err(js_error)?;
+    }
+
+    index.save(&cache).await?;
+
+    Ok(())
+}
+
+async fn remove_entry(cache: Cache, hash: WebcHash) -> Result<(), Error> {
+    JsFuture::from(cache.delete_with_str(&entry_url(&hash)))
+        .await
+        .map_err(js_error)?;
+
+    let mut index = Index::load(&cache).await?;
+    if index.entries.remove(&hash.to_string()).is_some() {
+        index.save(&cache).await?;
+    }
+
+    Ok(())
+}
+
+/// Bookkeeping used to decide which packages to evict.
+///
+/// Note: The index is stored alongside the packages it describes. Updates
+/// from different threads may race, in which case the cache can temporarily
+/// hold more than its limit until the next save.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct Index {
+    entries: BTreeMap<String, IndexEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexEntry {
+    size: u64,
+    /// When this entry was last used, in milliseconds since the Unix epoch.
+    last_used: f64,
+}
+
+impl Index {
+    async fn load(cache: &Cache) -> Result<Self, Error> {
+        let response = JsFuture::from(cache.match_with_str(INDEX_URL))
+            .await
+            .map_err(js_error)?;
+        if response.is_undefined() {
+            return Ok(Index::default());
+        }
+        let response: Response = response.unchecked_into();
+        let text = JsFuture::from(response.text().map_err(js_error)?)
+            .await
+            .map_err(js_error)?;
+        let text = text.as_string().unwrap_or_default();
+
+        let index = js_sys::JSON::parse(&text)
+            .ok()
+            .and_then(|value| serde_wasm_bindgen::from_value(value).ok());
+
+        match index {
+            Some(index) => Ok(index),
+            None => {
+                tracing::warn!("The package cache's index was corrupted. Starting afresh.");
+                Ok(Index::default())
+            }
+        }
+    }
+
+    async fn save(&self, cache: &Cache) -> Result<(), Error> {
+        let value = self
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| js_error(e.into()))?;
+        let text = js_sys::JSON::stringify(&value).map_err(js_error)?;
+        let response = Response::new_with_opt_str(text.as_string().as_deref()).map_err(js_error)?;
+
+        JsFuture::from(cache.put_with_str(INDEX_URL, &response))
+            .await
+            .map_err(js_error)?;
+
+        Ok(())
+    }
+
+    fn touch(&mut self, hash: &WebcHash, size: u64, now: f64) {
+        self.entries.insert(
+            hash.to_string(),
+            IndexEntry {
+                size,
+                last_used: now,
+            },
+        );
+    }
+
+    /// Remove the least recently used entries until the total size is no
+    /// more than `max_size`, returning the keys that were removed.
+    fn evict(&mut self, max_size: u64) -> Vec<String> {
+        let mut total: u64 = self.entries.values().map(|e| e.size).sum();
+        if total <= max_size {
+            return Vec::new();
+        }
+
+        let mut by_age: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect();
+        by_age.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut evicted = Vec::new();
+        for (_, key) in by_age {
+            if total <= max_size {
+                break;
+            }
+            if let Some(entry) = self.entries.remove(&key) {
+                total -= entry.size;
+                evicted.push(key);
+            }
+        }
+
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn evict_the_least_recently_used_entries() {
+        let mut index = Index::default();
+        index.touch(&WebcHash::sha256("a"), 40, 1.0);
+        index.touch(&WebcHash::sha256("b"), 40, 3.0);
+        index.touch(&WebcHash::sha256("c"), 40, 2.0);
+
+        let evicted = index.evict(100);
+
+        assert_eq!(evicted, vec![WebcHash::sha256("a").to_string()]);
+        assert_eq!(index.entries.len(), 2);
+        assert!(index.evict(100).is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn memory_cache_round_trip() {
+        let cache = MemoryCache::default();
+        let bytes = Bytes::from_static(b"webc");
+        let hash = WebcHash::sha256(&bytes);
+
+        assert!(cache.load(&hash).await.unwrap().is_none());
+        cache.save(hash, bytes.clone()).await.unwrap();
+        assert_eq!(cache.load(&hash).await.unwrap(), Some(bytes));
+
+        cache.remove(&hash).await.unwrap();
+        assert!(cache.load(&hash).await.unwrap().is_none());
+    }
+
+    #[wasm_bindgen_test]
+    async fn refuse_to_save_packages_with_the_wrong_hash() {
+        let cache = MemoryCache::default();
+        let hash = WebcHash::sha256(b"expected");
+
+        let err = cache
+            .save(hash, Bytes::from_static(b"actual"))
+            .await
+            .unwrap_err();
+
+        let err = err.downcast::<IntegrityError>().unwrap();
+        assert_eq!(err.expected, hash);
+        assert_eq!(err.actual, WebcHash::sha256(b"actual"));
+        assert!(cache.load(&hash).await.unwrap().is_none());
+    }
+}
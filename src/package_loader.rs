@@ -1,11 +1,11 @@
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
-};
+use std::{fmt::Display, sync::Arc};
 
 use anyhow::{Context, Error};
 use bytes::Bytes;
-use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use http::{header::ACCEPT, HeaderMap, HeaderValue, Method, StatusCode};
+use js_sys::{JsString, Reflect};
+use url::Url;
+use wasm_bindgen::prelude::wasm_bindgen;
 use wasmer_wasix::{
     bin_factory::BinaryPackage,
     http::{HttpClient, HttpRequest, HttpResponse},
@@ -13,29 +13,84 @@ use wasmer_wasix::{
 };
 use webc::Container;
 
+use crate::package_cache::{MemoryCache, PackageCache};
+
 /// A package loader that uses the browser's native APIs to download packages.
 ///
-/// Downloads will be cached based on the [`default`] caching behaviour.
+/// Downloads will be cached based on the [`default`] caching behaviour, and
+/// downloaded packages are kept in a [`PackageCache`] (in memory, unless
+/// told otherwise).
+///
+/// Downloaded packages are always checked against their expected hash. The
+/// [`PackageCache`] does the same when loading them, so corrupted cache
+/// entries are evicted and downloaded again.
 ///
 /// [`default`]: https://developer.mozilla.org/en-US/docs/Web/API/Request/cache
 #[derive(Debug, Clone)]
 pub struct PackageLoader {
     client: Arc<dyn HttpClient + Send + Sync>,
-    cache: Arc<Cache>,
+    cache: Arc<dyn PackageCache>,
 }
 
 impl PackageLoader {
     pub fn new(client: Arc<dyn HttpClient + Send + Sync>) -> Self {
-        let cache = Arc::new(Cache::default());
+        PackageLoader::with_cache(client, Arc::new(MemoryCache::default()))
+    }
+
+    pub(crate) fn with_cache(
+        client: Arc<dyn HttpClient + Send + Sync>,
+        cache: Arc<dyn PackageCache>,
+    ) -> Self {
         PackageLoader { client, cache }
     }
 
-    async fn download(&self, dist: &DistributionInfo) -> Result<Bytes, Error> {
-        let mut headers = HeaderMap::new();
-        headers.insert("Accept", HeaderValue::from_static("application/webc"));
+    /// Download a package from an arbitrary URL.
+    ///
+    /// If the package's hash is known up front, the download will be checked
+    /// against it and a cached copy may be used instead. Otherwise, the
+    /// package is always downloaded.
+    pub(crate) async fn download_url(
+        &self,
+        url: &Url,
+        headers: HeaderMap,
+        hash: Option<WebcHash>,
+    ) -> Result<Bytes, Error> {
+        if let Some(webc_sha256) = hash {
+            let dist = DistributionInfo {
+                webc: url.clone(),
+                webc_sha256,
+            };
+            return self.download_cached(&dist, headers).await;
+        }
+
+        let body = self.fetch(url, headers).await?;
+
+        // Note: We have nothing to look the package up by next time, but this
+        // lets a later load which knows the package's hash skip the download.
+        if let Err(e) = self.cache.save(WebcHash::sha256(&body), body.clone()).await {
+            tracing::warn!(error = &*e, "Unable to save to the package cache");
+        }
+
+        Ok(body)
+    }
+
+    async fn download(&self, dist: &DistributionInfo, headers: HeaderMap) -> Result<Bytes, Error> {
+        let url = &dist.webc;
+        let body = self.fetch(url, headers).await?;
+
+        IntegrityError::check(dist.webc_sha256, &body)
+            .with_context(|| format!("The package downloaded from \"{url}\" was corrupted"))?;
+
+        Ok(body)
+    }
+
+    async fn fetch(&self, url: &Url, mut headers: HeaderMap) -> Result<Bytes, Error> {
+        if !headers.contains_key(ACCEPT) {
+            headers.insert(ACCEPT, HeaderValue::from_static("application/webc"));
+        }
 
         let request = HttpRequest {
-            url: dist.webc.clone(),
+            url: url.clone(),
             method: Method::GET,
             headers,
             body: None,
@@ -56,7 +111,6 @@ impl PackageLoader {
         );
 
         if !response.is_ok() {
-            let url = &dist.webc;
             return Err(
                 http_error(&response).context(format!("The GET request to \"{url}\" failed"))
             );
@@ -69,18 +123,29 @@ impl PackageLoader {
         Ok(body.into())
     }
 
-    pub(crate) async fn download_cached(&self, dist: &DistributionInfo) -> Result<Bytes, Error> {
+    async fn download_cached(
+        &self,
+        dist: &DistributionInfo,
+        headers: HeaderMap,
+    ) -> Result<Bytes, Error> {
         let webc_hash = dist.webc_sha256;
 
-        let body = match self.cache.load(&webc_hash) {
+        let cached = self.cache.load(&webc_hash).await.unwrap_or_else(|e| {
+            tracing::warn!(error = &*e, "Unable to read from the package cache");
+            None
+        });
+
+        let body = match cached {
             Some(body) => {
                 tracing::debug!("Cache Hit!");
                 body
             }
             None => {
                 tracing::debug!("Cache Miss");
-                let bytes = self.download(dist).await?;
-                self.cache.save(webc_hash, bytes.clone());
+                let bytes = self.download(dist, headers).await?;
+                if let Err(e) = self.cache.save(webc_hash, bytes.clone()).await {
+                    tracing::warn!(error = &*e, "Unable to save to the package cache");
+                }
                 bytes
             }
         };
@@ -100,7 +165,9 @@ impl wasmer_wasix::runtime::package_loader::PackageLoader for PackageLoader {
         ),
     )]
     async fn load(&self, summary: &PackageSummary) -> Result<Container, Error> {
-        let body = self.download_cached(&summary.dist).await?;
+        let body = self
+            .download_cached(&summary.dist, HeaderMap::new())
+            .await?;
         let container = Container::from_bytes(body)?;
 
         Ok(container)
@@ -115,6 +182,70 @@ impl wasmer_wasix::runtime::package_loader::PackageLoader for PackageLoader {
     }
 }
 
+#[wasm_bindgen(typescript_custom_section)]
+const INTEGRITY_ERROR_TYPE_DECLARATION: &str = r#"
+/**
+ * The error thrown when a package's contents don't match the hash it was
+ * expected to have (e.g. because the download was corrupted).
+ */
+export type IntegrityError = Error & {
+    name: "IntegrityError";
+    /** The SHA-256 hash the package should have had. */
+    expected: string;
+    /** The package's actual SHA-256 hash. */
+    actual: string;
+};
+"#;
+
+/// The error returned when a package's contents don't match the
+/// [`WebcHash`] it was expected to have.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct IntegrityError {
+    pub expected: WebcHash,
+    pub actual: WebcHash,
+}
+
+impl IntegrityError {
+    /// Make sure some bytes have the `expected` hash.
+    pub(crate) fn check(expected: WebcHash, bytes: &[u8]) -> Result<(), IntegrityError> {
+        let actual = WebcHash::sha256(bytes);
+
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(IntegrityError { expected, actual })
+        }
+    }
+
+    /// Turn a JavaScript `Error` into an `IntegrityError` with `expected`
+    /// and `actual` properties.
+    pub(crate) fn annotate(&self, error: &js_sys::Error) {
+        error.set_name("IntegrityError");
+        let _ = Reflect::set(
+            error,
+            &JsString::from("expected"),
+            &JsString::from(self.expected.to_string()),
+        );
+        let _ = Reflect::set(
+            error,
+            &JsString::from("actual"),
+            &JsString::from(self.actual.to_string()),
+        );
+    }
+}
+
+impl Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Integrity check failed (expected a SHA-256 hash of {}, but found {})",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
 pub(crate) fn http_error(response: &HttpResponse) -> Error {
     let status = response.status;
 
@@ -134,29 +265,3 @@ pub(crate) fn http_error(response: &HttpResponse) -> Error {
 
     Error::msg(status)
 }
-
-/// A quick'n'dirty cache for downloaded packages.
-///
-/// This makes no attempt at verifying a cached
-#[derive(Debug, Default)]
-struct Cache(Mutex<HashMap<WebcHash, Bytes>>);
-
-impl Cache {
-    fn load(&self, hash: &WebcHash) -> Option<Bytes> {
-        let cache = self.0.lock().ok()?;
-        let bytes = cache.get(hash)?;
-        Some(bytes.clone())
-    }
-
-    fn save(&self, hash: WebcHash, bytes: Bytes) {
-        debug_assert_eq!(
-            hash,
-            WebcHash::sha256(bytes.as_ref()),
-            "Mismatched webc hash"
-        );
-
-        if let Ok(mut cache) = self.0.lock() {
-            cache.insert(hash, bytes);
-        }
-    }
-}
@@ -1,8 +1,15 @@
-use futures::channel::oneshot;
+use std::sync::Arc;
+
+use futures::{channel::oneshot, FutureExt};
 use wasm_bindgen::{prelude::wasm_bindgen, JsCast};
 use wasmer_wasix::{Runtime as _, WasiEnvBuilder};
 
-use crate::{instance::ExitCondition, utils::Error, Instance, RunOptions};
+use crate::{
+    instance::{run_with_process_handle, ProcessHandle},
+    tty::TtyState,
+    utils::Error,
+    Instance, RunOptions,
+};
 
 const DEFAULT_PROGRAM_NAME: &str = "wasm";
 
@@ -28,6 +35,8 @@ pub async fn run_wasix(wasm_module: WasmModule, config: RunOptions) -> Result<In
 #[tracing::instrument(level = "debug", skip_all)]
 async fn run_wasix_inner(wasm_module: WasmModule, config: RunOptions) -> Result<Instance, Error> {
     let runtime = config.runtime().resolve()?.into_inner();
+    let tty = TtyState::default();
+    let runtime = Arc::new(runtime.with_tty(tty.clone()));
 
     let program_name = config
         .program()
@@ -35,9 +44,11 @@ async fn run_wasix_inner(wasm_module: WasmModule, config: RunOptions) -> Result<
         .unwrap_or_else(|| DEFAULT_PROGRAM_NAME.to_string());
 
     let mut builder = WasiEnvBuilder::new(program_name).runtime(runtime.clone());
-    let (stdin, stdout, stderr) = config.configure_builder(&mut builder)?;
+    let (stdin, stdout, stderr, output) = config.configure_builder(&mut builder, &tty)?;
 
     let (exit_code_tx, exit_code_rx) = oneshot::channel();
+    let process = ProcessHandle::default();
+    let timeout = config.parse_timeout()?;
 
     let module: wasmer::Module = wasm_module.to_module(&*runtime).await?;
 
@@ -46,19 +57,33 @@ async fn run_wasix_inner(wasm_module: WasmModule, config: RunOptions) -> Result<
     let tasks = runtime.task_manager().clone();
     tasks.spawn_with_module(
         module,
-        Box::new(move |module| {
-            let _span = tracing::debug_span!("run").entered();
-            let result = builder.run(module).map_err(anyhow::Error::new);
-            let _ = exit_code_tx.send(ExitCondition::from_result(result));
+        Box::new({
+            let process = process.clone();
+            move |module| {
+                let _span = tracing::debug_span!("run").entered();
+                let exit_condition = run_with_process_handle(builder, module, &*runtime, &process);
+                let _ = exit_code_tx.send(exit_condition);
+            }
         }),
     )?;
 
-    Ok(Instance {
+    if let Some(timeout) = timeout {
+        process.kill_after(timeout);
+    }
+
+    let mut instance = Instance {
         stdin,
         stdout,
         stderr,
-        exit: exit_code_rx,
-    })
+        output,
+        exit: exit_code_rx.shared(),
+        process,
+        tty,
+        forwarded: None,
+    };
+    instance.forward_output(config.on_stdout(), config.on_stderr());
+
+    Ok(instance)
 }
 
 #[wasm_bindgen]
@@ -1,20 +1,19 @@
-use std::sync::{atomic::AtomicBool, Arc, Mutex, Weak};
+use std::sync::{Arc, Mutex, Weak};
 
 use http::HeaderValue;
 use once_cell::sync::Lazy;
 use virtual_net::VirtualNetworking;
 use wasmer_wasix::{
     http::{HttpClient, WebHttpClient},
-    os::{TtyBridge, TtyOptions},
     runtime::{
-        module_cache::ThreadLocalCache,
+        module_cache::{ModuleCache, ThreadLocalCache},
         package_loader::PackageLoader,
         resolver::{PackageSpecifier, PackageSummary, QueryError, Source, WapmSource},
     },
-    VirtualTaskManager, WasiTtyState,
+    VirtualTaskManager,
 };
 
-use crate::{tasks::ThreadPool, utils::Error};
+use crate::{package_cache::PackageCache, tasks::ThreadPool, tty::TtyState, utils::Error};
 
 /// A weak reference to the global [`Runtime`].
 static GLOBAL_RUNTIME: Lazy<Mutex<Weak<Runtime>>> = Lazy::new(Mutex::default);
@@ -29,9 +28,8 @@ pub struct Runtime {
     source: Option<Arc<WapmSource>>,
     http_client: Arc<dyn HttpClient + Send + Sync>,
     package_loader: Arc<crate::package_loader::PackageLoader>,
-    module_cache: Arc<ThreadLocalCache>,
-    tty: TtyOptions,
-    connected_to_tty: Arc<AtomicBool>,
+    module_cache: Arc<dyn ModuleCache + Send + Sync>,
+    tty: TtyState,
 }
 
 impl Runtime {
@@ -101,8 +99,7 @@ impl Runtime {
             http_client: Arc::new(http_client),
             package_loader: Arc::new(package_loader),
             module_cache: Arc::new(module_cache),
-            tty: TtyOptions::default(),
-            connected_to_tty: Arc::new(AtomicBool::new(false)),
+            tty: TtyState::default(),
         }
     }
 
@@ -119,6 +116,29 @@ impl Runtime {
         Ok(())
     }
 
+    /// The [`ThreadPool`] used to run tasks in the background.
+    pub(crate) fn thread_pool(&self) -> &ThreadPool {
+        &self.pool
+    }
+
+    /// The [`PackageLoader`][crate::package_loader::PackageLoader] used to
+    /// download packages.
+    pub(crate) fn package_downloader(&self) -> &crate::package_loader::PackageLoader {
+        &self.package_loader
+    }
+
+    /// Change where downloaded packages are cached.
+    pub(crate) fn set_package_cache(&mut self, cache: Arc<dyn PackageCache>) {
+        let loader =
+            crate::package_loader::PackageLoader::with_cache(self.http_client.clone(), cache);
+        self.package_loader = Arc::new(loader);
+    }
+
+    /// Change where compiled WebAssembly modules are cached.
+    pub(crate) fn set_module_cache(&mut self, cache: Arc<dyn ModuleCache + Send + Sync>) {
+        self.module_cache = cache;
+    }
+
     /// Enable networking (i.e. TCP and UDP) via a gateway server.
     pub fn set_network_gateway(&mut self, gateway_url: String) {
         let networking = crate::net::connect_networking(gateway_url);
@@ -127,13 +147,16 @@ impl Runtime {
 }
 
 impl Runtime {
-    pub(crate) fn tty_options(&self) -> &TtyOptions {
-        &self.tty
-    }
-
-    pub(crate) fn set_connected_to_tty(&self, state: bool) {
-        self.connected_to_tty
-            .store(state, std::sync::atomic::Ordering::SeqCst);
+    /// Get a copy of this runtime which reports the provided TTY state to
+    /// the programs it runs.
+    ///
+    /// Everything else (the thread pool, caches, networking, etc.) is still
+    /// shared with the original runtime.
+    pub(crate) fn with_tty(&self, tty: TtyState) -> Runtime {
+        Runtime {
+            tty,
+            ..self.clone()
+        }
     }
 }
 
@@ -161,72 +184,16 @@ impl wasmer_wasix::runtime::Runtime for Runtime {
         self.package_loader.clone()
     }
 
-    fn module_cache(
-        &self,
-    ) -> Arc<dyn wasmer_wasix::runtime::module_cache::ModuleCache + Send + Sync> {
+    fn module_cache(&self) -> Arc<dyn ModuleCache + Send + Sync> {
         self.module_cache.clone()
     }
 
     fn load_module_sync(&self, wasm: &[u8]) -> Result<wasmer::Module, anyhow::Error> {
-        let wasm = unsafe { js_sys::Uint8Array::view(wasm) };
-        let module = js_sys::WebAssembly::Module::new(&wasm).map_err(crate::utils::js_error)?;
-        // Note: We need to use this From impl because it will use the
-        // wasm-types-polyfill to parse the *.wasm file's import section.
-        //
-        // The browser doesn't give you any way to inspect the imports at the
-        // moment, so without the polyfill we'll always assume the module wants
-        // a minimum of 1 page of memory. This causes modules that want more
-        // memory by default (e.g. sharrattj/bash) to fail with an instantiation
-        // error.
-        //
-        // https://github.com/wasmerio/wasmer/blob/8ec4f1d76062e2a612ac2f70f4a73eaf59f8fe9f/lib/api/src/js/module.rs#L323-L328
-        Ok(wasmer::Module::from((module, wasm.to_vec())))
+        crate::module_cache::compile(wasm)
     }
 
     fn tty(&self) -> Option<&(dyn wasmer_wasix::os::TtyBridge + Send + Sync)> {
-        Some(self)
-    }
-}
-
-impl TtyBridge for Runtime {
-    #[tracing::instrument(level = "debug", skip_all)]
-    fn reset(&self) {
-        self.tty.set_echo(true);
-        self.tty.set_line_buffering(true);
-        self.tty.set_line_feeds(true);
-        self.set_connected_to_tty(false);
-    }
-
-    #[tracing::instrument(level = "debug", skip(self), ret)]
-    fn tty_get(&self) -> WasiTtyState {
-        let connected_to_tty = self
-            .connected_to_tty
-            .load(std::sync::atomic::Ordering::SeqCst);
-
-        WasiTtyState {
-            cols: self.tty.cols(),
-            rows: self.tty.rows(),
-            width: 800,
-            height: 600,
-            stdin_tty: connected_to_tty,
-            stdout_tty: connected_to_tty,
-            stderr_tty: connected_to_tty,
-            echo: self.tty.echo(),
-            line_buffered: self.tty.line_buffering(),
-            line_feeds: self.tty.line_feeds(),
-        }
-    }
-
-    #[tracing::instrument(level = "debug", skip(self))]
-    fn tty_set(&self, tty_state: WasiTtyState) {
-        self.tty.set_cols(tty_state.cols);
-        self.tty.set_rows(tty_state.rows);
-        self.tty.set_echo(tty_state.echo);
-        self.tty.set_line_buffering(tty_state.line_buffered);
-        self.tty.set_line_feeds(tty_state.line_feeds);
-        self.set_connected_to_tty(
-            tty_state.stdin_tty || tty_state.stdout_tty || tty_state.stderr_tty,
-        );
+        Some(&self.tty)
     }
 }
 
@@ -1,9 +1,25 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::Poll,
+};
+
 use anyhow::Context;
 use bytes::BytesMut;
-use futures::{future::Either, Stream};
-use js_sys::{JsString, Promise, Reflect, Uint8Array};
+use futures::{
+    channel::mpsc,
+    future::{BoxFuture, Either},
+    Stream, StreamExt,
+};
+use js_sys::{ArrayBuffer, AsyncIterator, IteratorNext, JsString, Promise, Reflect, Uint8Array};
+use tokio::io::ReadBuf;
 use tracing::Instrument;
-use virtual_fs::{AsyncReadExt, AsyncWriteExt, Pipe};
+use virtual_fs::{
+    AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt, Pipe, VirtualFile,
+};
 use wasm_bindgen::{prelude::wasm_bindgen, JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{
@@ -12,31 +28,213 @@ use web_sys::{
 
 use crate::utils::Error;
 
+/// The maximum number of bytes that can be written to an [`input_pipe()`]
+/// before the WASIX process needs to read some of it.
+const MAX_INPUT_BACKLOG: usize = 1024 * 1024;
+
 /// Set up a pipe where data written from JavaScript can be read by the WASIX
 /// process.
-pub(crate) fn input_pipe() -> (Pipe, WritableStream) {
+///
+/// Writes will wait once the WASIX process falls too far behind, so the
+/// [`WritableStream`] applies backpressure.
+pub(crate) fn input_pipe() -> (InputPipe, WritableStream) {
     let (left, right) = Pipe::channel();
+    let backlog = Arc::new(Backlog::default());
 
-    let sink = JsValue::from(WritableStreamSink { pipe: right });
-
-    let callback: wasm_bindgen::prelude::Closure<dyn Fn(Uint8Array) -> f64> =
-        wasm_bindgen::closure::Closure::new(|chunk: Uint8Array| chunk.byte_length() as f64);
+    let sink = JsValue::from(WritableStreamSink {
+        pipe: right,
+        backlog: Arc::clone(&backlog),
+    });
 
     let stream = WritableStream::new_with_underlying_sink_and_strategy(
         sink.unchecked_ref(),
-        web_sys::QueuingStrategy::new()
-            .high_water_mark(256.0)
-            .size(callback.into_js_value().unchecked_ref()),
+        &byte_length_strategy(256.0),
     )
     .unwrap();
 
-    (left, stream)
+    let pipe = InputPipe {
+        pipe: left,
+        backlog,
+    };
+
+    (pipe, stream)
+}
+
+/// Pipe a JavaScript stream into a file the WASIX process can read from.
+pub(crate) fn readable_stream_pipe(stream: ReadableStream) -> InputPipe {
+    let (pipe, writable) = input_pipe();
+
+    let piped = JsFuture::from(stream.pipe_to(&writable));
+    wasm_bindgen_futures::spawn_local(
+        async move {
+            match piped.await {
+                Ok(_) => tracing::debug!("Finished piping the stream"),
+                Err(e) => {
+                    let e = Error::js(e).into_anyhow();
+                    tracing::debug!(error = &*e, "Unable to pipe the stream");
+                }
+            }
+        }
+        .in_current_span(),
+    );
+
+    pipe
+}
+
+/// Bookkeeping used to stop JavaScript from writing to an [`InputPipe`] faster
+/// than the WASIX process can read.
+#[derive(Debug, Default)]
+struct Backlog {
+    /// How many bytes have been written but not yet read.
+    pending: AtomicUsize,
+    /// Set once the reading end has been dropped.
+    closed: AtomicBool,
+    changed: tokio::sync::Notify,
+}
+
+impl Backlog {
+    /// Wait until the backlog has been drained below [`MAX_INPUT_BACKLOG`].
+    async fn drained(&self) -> Result<(), Error> {
+        loop {
+            // Note: Create the future before checking so we can't miss a
+            // notification.
+            let changed = self.changed.notified();
+
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(anyhow::anyhow!("The pipe was closed").into());
+            }
+            if self.pending.load(Ordering::SeqCst) <= MAX_INPUT_BACKLOG {
+                return Ok(());
+            }
+
+            changed.await;
+        }
+    }
+
+    fn consumed(&self, bytes: usize) {
+        // Note: saturate in case anything bypassed the sink (e.g. a TTY)
+        let _ = self
+            .pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                Some(pending.saturating_sub(bytes))
+            });
+        self.changed.notify_waiters();
+    }
+}
+
+/// The end of an [`input_pipe()`] the WASIX process reads from.
+#[derive(Debug)]
+pub(crate) struct InputPipe {
+    pipe: Pipe,
+    backlog: Arc<Backlog>,
+}
+
+impl Drop for InputPipe {
+    fn drop(&mut self) {
+        self.backlog.closed.store(true, Ordering::SeqCst);
+        self.backlog.changed.notify_waiters();
+    }
+}
+
+impl VirtualFile for InputPipe {
+    fn last_accessed(&self) -> u64 {
+        self.pipe.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.pipe.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.pipe.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.pipe.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> virtual_fs::Result<()> {
+        self.pipe.set_len(new_size)
+    }
+
+    fn unlink(&mut self) -> BoxFuture<'static, virtual_fs::Result<()>> {
+        self.pipe.unlink()
+    }
+
+    fn poll_read_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.pipe).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.pipe).poll_write_ready(cx)
+    }
+}
+
+impl AsyncRead for InputPipe {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut self.pipe).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(_)) = result {
+            self.backlog.consumed(buf.filled().len() - filled_before);
+        }
+
+        result
+    }
+}
+
+impl AsyncWrite for InputPipe {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.pipe).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_shutdown(cx)
+    }
+}
+
+impl AsyncSeek for InputPipe {
+    fn start_seek(mut self: Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
+        Pin::new(&mut self.pipe).start_seek(position)
+    }
+
+    fn poll_complete(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<u64>> {
+        Pin::new(&mut self.pipe).poll_complete(cx)
+    }
 }
 
 #[derive(Debug)]
 #[wasm_bindgen(skip_typescript)]
 struct WritableStreamSink {
     pipe: Pipe,
+    backlog: Arc<Backlog>,
 }
 
 #[wasm_bindgen]
@@ -86,6 +284,7 @@ impl WritableStreamSink {
     /// aborted (see below).
     pub fn write(&mut self, chunk: Uint8Array) -> Promise {
         let mut pipe = self.pipe.clone();
+        let backlog = Arc::clone(&self.backlog);
         let data = chunk.to_vec();
 
         wasm_bindgen_futures::future_to_promise(
@@ -96,10 +295,16 @@ impl WritableStreamSink {
                     data_utf8 = String::from_utf8_lossy(&data).as_ref(),
                 );
 
+                backlog.pending.fetch_add(data.len(), Ordering::SeqCst);
                 pipe.write_all(&data)
                     .await
                     .context("Write failed")
                     .map_err(Error::from)?;
+
+                // Don't accept any more data until the WASIX process has
+                // caught up
+                backlog.drained().await?;
+
                 Ok(JsValue::UNDEFINED)
             }
             .in_current_span()
@@ -115,14 +320,9 @@ pub(crate) fn output_pipe() -> (Pipe, ReadableStream) {
 
     let source = JsValue::from(ReadableStreamSource { pipe: right });
 
-    let callback: wasm_bindgen::prelude::Closure<dyn Fn(Uint8Array) -> f64> =
-        wasm_bindgen::closure::Closure::new(|chunk: Uint8Array| chunk.byte_length() as f64);
-
     let stream = ReadableStream::new_with_underlying_source_and_strategy(
         source.unchecked_ref(),
-        web_sys::QueuingStrategy::new()
-            .high_water_mark(256.0)
-            .size(callback.into_js_value().unchecked_ref()),
+        &byte_length_strategy(256.0),
     )
     .unwrap();
 
@@ -226,7 +426,651 @@ impl ReadableStreamSource {
     }
 }
 
+/// A [`web_sys::QueuingStrategy`] which measures chunks by their length in
+/// bytes.
+fn byte_length_strategy(high_water_mark: f64) -> web_sys::QueuingStrategy {
+    let callback: wasm_bindgen::prelude::Closure<dyn Fn(Uint8Array) -> f64> =
+        wasm_bindgen::closure::Closure::new(|chunk: Uint8Array| chunk.byte_length() as f64);
+
+    let mut strategy = web_sys::QueuingStrategy::new();
+    strategy
+        .high_water_mark(high_water_mark)
+        .size(callback.into_js_value().unchecked_ref());
+    strategy
+}
+
+/// How much data will be buffered by streams reading from or writing to a
+/// file.
+const FILE_HIGH_WATER_MARK: f64 = 64.0 * 1024.0;
+
+type FileSlot = Arc<futures::lock::Mutex<Option<Box<dyn VirtualFile + Send + Sync>>>>;
+
+/// Create a [`ReadableStream`] which reads from a file on demand.
+///
+/// Unlike [`output_pipe()`], data is only read from the file when JavaScript
+/// asks for more, so large files never need to be buffered in memory.
+pub(crate) fn file_read_stream(file: Box<dyn VirtualFile + Send + Sync>) -> ReadableStream {
+    let source = JsValue::from(FileSource {
+        file: Arc::new(futures::lock::Mutex::new(Some(file))),
+    });
+
+    ReadableStream::new_with_underlying_source_and_strategy(
+        source.unchecked_ref(),
+        &byte_length_strategy(FILE_HIGH_WATER_MARK),
+    )
+    .unwrap()
+}
+
+#[derive(Debug)]
+#[wasm_bindgen(skip_typescript)]
+struct FileSource {
+    file: FileSlot,
+}
+
+#[wasm_bindgen]
+impl FileSource {
+    /// Read the next chunk from the file (see
+    /// [`ReadableStreamSource::pull()`]).
+    pub fn pull(&mut self, controller: ReadableStreamDefaultController) -> Promise {
+        let file = Arc::clone(&self.file);
+
+        wasm_bindgen_futures::future_to_promise(
+            async move {
+                let mut file = file.lock().await;
+                let Some(f) = file.as_mut() else {
+                    // The stream was cancelled
+                    return Ok(JsValue::UNDEFINED);
+                };
+
+                let capacity = controller
+                    .desired_size()
+                    .filter(|size| *size > 0.0)
+                    .map(|size| size as usize)
+                    .unwrap_or(FILE_HIGH_WATER_MARK as usize);
+                let mut buffer = BytesMut::with_capacity(capacity);
+
+                match f.read_buf(&mut buffer).await.context("Read failed") {
+                    Ok(0) => {
+                        tracing::debug!("EOF");
+                        file.take();
+                        controller.close()?;
+                    }
+                    Ok(bytes_read) => {
+                        tracing::trace!(bytes_read);
+                        let buffer = Uint8Array::from(&buffer[..bytes_read]);
+                        controller.enqueue_with_chunk(&buffer)?;
+                    }
+                    Err(e) => {
+                        tracing::debug!(error = &*e);
+                        file.take();
+                        let err = JsValue::from(Error::from(e));
+                        controller.error_with_e(&err);
+                    }
+                }
+
+                Ok(JsValue::UNDEFINED)
+            }
+            .in_current_span()
+            .instrument(tracing::trace_span!("pull")),
+        )
+    }
+
+    /// Close the file early (see [`ReadableStreamSource::cancel()`]).
+    pub fn cancel(&mut self) -> Promise {
+        let file = Arc::clone(&self.file);
+
+        wasm_bindgen_futures::future_to_promise(async move {
+            tracing::debug!("File read stream cancelled");
+            file.lock().await.take();
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+}
+
+/// Create a [`WritableStream`] which writes each chunk it is given to a file.
+///
+/// The file is flushed and closed when the stream is closed.
+pub(crate) fn file_write_stream(file: Box<dyn VirtualFile + Send + Sync>) -> WritableStream {
+    let sink = JsValue::from(FileSink {
+        file: Arc::new(futures::lock::Mutex::new(Some(file))),
+    });
+
+    WritableStream::new_with_underlying_sink_and_strategy(
+        sink.unchecked_ref(),
+        &byte_length_strategy(FILE_HIGH_WATER_MARK),
+    )
+    .unwrap()
+}
+
+#[derive(Debug)]
+#[wasm_bindgen(skip_typescript)]
+struct FileSink {
+    file: FileSlot,
+}
+
+#[wasm_bindgen]
+impl FileSink {
+    /// Write a chunk to the file (see [`WritableStreamSink::write()`]).
+    pub fn write(&mut self, chunk: Uint8Array) -> Promise {
+        let file = Arc::clone(&self.file);
+        let data = chunk.to_vec();
+
+        wasm_bindgen_futures::future_to_promise(
+            async move {
+                tracing::trace!(bytes_written = data.len());
+
+                let mut file = file.lock().await;
+                let f = file
+                    .as_mut()
+                    .context("The file was closed")
+                    .map_err(Error::from)?;
+                f.write_all(&data)
+                    .await
+                    .context("Write failed")
+                    .map_err(Error::from)?;
+
+                Ok(JsValue::UNDEFINED)
+            }
+            .in_current_span()
+            .instrument(tracing::trace_span!("write")),
+        )
+    }
+
+    /// Flush and close the file (see [`WritableStreamSink::close()`]).
+    pub fn close(&mut self) -> Promise {
+        let file = Arc::clone(&self.file);
+
+        wasm_bindgen_futures::future_to_promise(
+            async move {
+                if let Some(mut f) = file.lock().await.take() {
+                    f.flush()
+                        .await
+                        .context("Flushing failed")
+                        .map_err(Error::from)?;
+                }
+                tracing::debug!("File closed");
+
+                Ok(JsValue::UNDEFINED)
+            }
+            .in_current_span()
+            .instrument(tracing::debug_span!("close")),
+        )
+    }
+
+    /// Close the file without flushing (see [`WritableStreamSink::abort()`]).
+    pub fn abort(&mut self, reason: JsValue) -> Promise {
+        let file = Arc::clone(&self.file);
+
+        wasm_bindgen_futures::future_to_promise(async move {
+            tracing::debug!(?reason, "Aborting the file write stream");
+            file.lock().await.take();
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+}
+
+/// An [`output_pipe()`] which has already been closed, for when a program's
+/// output is being sent somewhere else.
+pub(crate) fn closed_output_stream() -> ReadableStream {
+    let (pipe, stream) = output_pipe();
+    pipe.close();
+    stream
+}
+
+/// Which stream a chunk of output was written to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum OutputKind {
+    Stdout,
+    Stderr,
+}
+
+impl OutputKind {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            OutputKind::Stdout => "stdout",
+            OutputKind::Stderr => "stderr",
+        }
+    }
+}
+
+type TaggedChunk = (OutputKind, Vec<u8>);
+
+/// The number of chunks a [`merged_output_pipe()`] will buffer before the
+/// WASIX process needs to wait for JavaScript to read some of them.
+const MERGED_OUTPUT_BACKLOG: usize = 16;
+
+/// Set up a pair of pipes the WASIX program can use as stdout and stderr,
+/// where everything they write is merged into a single [`ReadableStream`] of
+/// `{ stream, data }` chunks.
+///
+/// Because both pipes share one channel, chunks are seen by JavaScript in the
+/// order they were written. Writes will wait once JavaScript falls too far
+/// behind, just like with an [`output_pipe()`].
+pub(crate) fn merged_output_pipe() -> (TaggedPipe, TaggedPipe, ReadableStream) {
+    let (sender, receiver) = mpsc::channel(MERGED_OUTPUT_BACKLOG);
+
+    let stdout = TaggedPipe {
+        kind: OutputKind::Stdout,
+        sender: Some(sender.clone()),
+    };
+    let stderr = TaggedPipe {
+        kind: OutputKind::Stderr,
+        sender: Some(sender),
+    };
+
+    let source = JsValue::from(MergedOutputSource {
+        receiver: Arc::new(futures::lock::Mutex::new(receiver)),
+    });
+    let stream = ReadableStream::new_with_underlying_source(source.unchecked_ref()).unwrap();
+
+    (stdout, stderr, stream)
+}
+
+/// One half of a [`merged_output_pipe()`].
+#[derive(Debug, Clone)]
+pub(crate) struct TaggedPipe {
+    kind: OutputKind,
+    sender: Option<mpsc::Sender<TaggedChunk>>,
+}
+
+impl TaggedPipe {
+    /// Wait until there is room in the channel for another chunk.
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<std::io::Result<()>> {
+        let result = match &mut self.sender {
+            Some(sender) => futures::ready!(sender.poll_ready(cx)),
+            None => return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into())),
+        };
+
+        Poll::Ready(result.map_err(|_| std::io::ErrorKind::BrokenPipe.into()))
+    }
+}
+
+impl VirtualFile for TaggedPipe {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+
+    fn last_modified(&self) -> u64 {
+        0
+    }
+
+    fn created_time(&self) -> u64 {
+        0
+    }
+
+    fn size(&self) -> u64 {
+        0
+    }
+
+    fn set_len(&mut self, _new_size: u64) -> virtual_fs::Result<()> {
+        Err(virtual_fs::FsError::PermissionDenied)
+    }
+
+    fn unlink(&mut self) -> BoxFuture<'static, virtual_fs::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn poll_read_ready(
+        self: Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(Ok(0))
+    }
+
+    fn poll_write_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<usize>> {
+        futures::ready!(self.poll_ready(cx))?;
+        Poll::Ready(Ok(8192))
+    }
+}
+
+impl AsyncRead for TaggedPipe {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        // Output pipes are write-only, so reads always hit EOF
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for TaggedPipe {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        futures::ready!(self.poll_ready(cx))?;
+
+        let kind = self.kind;
+        let sent = match &mut self.sender {
+            Some(sender) => sender.start_send((kind, buf.to_vec())).is_ok(),
+            None => false,
+        };
+
+        if sent {
+            Poll::Ready(Ok(buf.len()))
+        } else {
+            Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()))
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.sender.take();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for TaggedPipe {
+    fn start_seek(self: Pin<&mut Self>, _position: std::io::SeekFrom) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn poll_complete(
+        self: Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Ok(0))
+    }
+}
+
+#[derive(Debug)]
+#[wasm_bindgen(skip_typescript)]
+struct MergedOutputSource {
+    receiver: Arc<futures::lock::Mutex<mpsc::Receiver<TaggedChunk>>>,
+}
+
+#[wasm_bindgen]
+impl MergedOutputSource {
+    /// Pass the next chunk of output to the stream (see
+    /// [`ReadableStreamSource::pull()`]).
+    pub fn pull(&mut self, controller: ReadableStreamDefaultController) -> Promise {
+        let receiver = Arc::clone(&self.receiver);
+
+        wasm_bindgen_futures::future_to_promise(
+            async move {
+                let next = receiver.lock().await.next().await;
+
+                match next {
+                    Some((kind, data)) => {
+                        tracing::trace!(stream = kind.as_str(), bytes_read = data.len());
+
+                        let chunk = js_sys::Object::new();
+                        Reflect::set(&chunk, &"stream".into(), &kind.as_str().into())?;
+                        Reflect::set(&chunk, &"data".into(), &Uint8Array::from(&data[..]))?;
+                        controller.enqueue_with_chunk(&chunk)?;
+                    }
+                    None => {
+                        tracing::debug!("EOF");
+                        controller.close()?;
+                    }
+                }
+
+                Ok(JsValue::UNDEFINED)
+            }
+            .in_current_span()
+            .instrument(tracing::trace_span!("pull")),
+        )
+    }
+
+    /// Stop accepting output (see [`ReadableStreamSource::cancel()`]).
+    pub fn cancel(&mut self) -> Promise {
+        let receiver = Arc::clone(&self.receiver);
+
+        wasm_bindgen_futures::future_to_promise(async move {
+            tracing::debug!("Merged output stream cancelled");
+            receiver.lock().await.close();
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+}
+
+/// A [`VirtualFile`] that can be cloned, with every clone writing to the same
+/// underlying file (e.g. so a TTY can echo input to the program's stdout).
+#[derive(Debug, Clone)]
+pub(crate) struct SharedFile(Arc<Mutex<Box<dyn VirtualFile + Send + Sync>>>);
+
+impl SharedFile {
+    pub(crate) fn new(file: Box<dyn VirtualFile + Send + Sync>) -> Self {
+        SharedFile(Arc::new(Mutex::new(file)))
+    }
+
+    fn with<T>(&self, func: impl FnOnce(Pin<&mut (dyn VirtualFile + Send + Sync)>) -> T) -> T {
+        let mut file = self.0.lock().unwrap();
+        func(Pin::new(&mut **file))
+    }
+}
+
+impl VirtualFile for SharedFile {
+    fn last_accessed(&self) -> u64 {
+        self.0.lock().unwrap().last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.0.lock().unwrap().last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.0.lock().unwrap().created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.0.lock().unwrap().size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> virtual_fs::Result<()> {
+        self.0.lock().unwrap().set_len(new_size)
+    }
+
+    fn unlink(&mut self) -> BoxFuture<'static, virtual_fs::Result<()>> {
+        self.0.lock().unwrap().unlink()
+    }
+
+    fn poll_read_ready(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<usize>> {
+        self.with(|f| f.poll_read_ready(cx))
+    }
+
+    fn poll_write_ready(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<usize>> {
+        self.with(|f| f.poll_write_ready(cx))
+    }
+}
+
+impl AsyncRead for SharedFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.with(|f| f.poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for SharedFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.with(|f| f.poll_write(cx, buf))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.with(|f| f.poll_flush(cx))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.with(|f| f.poll_shutdown(cx))
+    }
+}
+
+impl AsyncSeek for SharedFile {
+    fn start_seek(self: Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
+        self.with(|f| f.start_seek(position))
+    }
+
+    fn poll_complete(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<u64>> {
+        self.with(|f| f.poll_complete(cx))
+    }
+}
+
+/// Create a [`ReadableStream`] which pulls chunks from a JavaScript
+/// [async iterator](https://developer.mozilla.org/en-US/docs/Web/JavaScript/Reference/Global_Objects/AsyncIterator).
+///
+/// Chunks may be strings (which are encoded as UTF-8), `ArrayBuffer`s, or any
+/// `ArrayBufferView` (e.g. a `Uint8Array` or `DataView`). Anything else is
+/// rejected with a `TypeError`.
+pub(crate) fn async_iterator_stream(iterator: AsyncIterator) -> ReadableStream {
+    let source = JsValue::from(AsyncIteratorSource { iterator });
+    ReadableStream::new_with_underlying_source(source.unchecked_ref()).unwrap()
+}
+
+#[derive(Debug)]
+#[wasm_bindgen(skip_typescript)]
+struct AsyncIteratorSource {
+    iterator: AsyncIterator,
+}
+
+#[wasm_bindgen]
+impl AsyncIteratorSource {
+    /// Pass the iterator's next item to the stream (see
+    /// [`ReadableStreamSource::pull()`]).
+    pub fn pull(&mut self, controller: ReadableStreamDefaultController) -> Promise {
+        let iterator = self.iterator.clone();
+
+        wasm_bindgen_futures::future_to_promise(
+            async move {
+                let next: IteratorNext = JsFuture::from(iterator.next()?).await?.unchecked_into();
+
+                if next.done() {
+                    tracing::debug!("EOF");
+                    controller.close()?;
+                    return Ok(JsValue::UNDEFINED);
+                }
+
+                let chunk = chunk_to_bytes(&next.value())?;
+                tracing::trace!(bytes_read = chunk.byte_length());
+                controller.enqueue_with_chunk(&chunk)?;
+
+                Ok(JsValue::UNDEFINED)
+            }
+            .in_current_span()
+            .instrument(tracing::trace_span!("pull")),
+        )
+    }
+
+    /// Give the iterator a chance to clean up (see
+    /// [`ReadableStreamSource::cancel()`]).
+    pub fn cancel(&mut self) -> Result<(), JsValue> {
+        tracing::debug!("Async iterator stream cancelled");
+
+        let return_ = Reflect::get(&self.iterator, &JsValue::from_str("return"))?;
+        if let Some(return_) = return_.dyn_ref::<js_sys::Function>() {
+            return_.call0(&self.iterator)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the bytes for a chunk yielded by an async iterator.
+fn chunk_to_bytes(value: &JsValue) -> Result<Uint8Array, JsValue> {
+    if let Some(s) = value.as_string() {
+        Ok(Uint8Array::from(s.as_bytes()))
+    } else if let Some(bytes) = value.dyn_ref::<Uint8Array>() {
+        Ok(Uint8Array::new(bytes))
+    } else if let Some(buffer) = value.dyn_ref::<ArrayBuffer>() {
+        Ok(Uint8Array::new(buffer))
+    } else if ArrayBuffer::is_view(value) {
+        // Note: Uint8Array::new() would convert each element of a typed array
+        // to a byte, so we need to look at the underlying memory instead.
+        let buffer = Reflect::get(value, &JsValue::from_str("buffer"))?;
+        let offset = Reflect::get(value, &JsValue::from_str("byteOffset"))?;
+        let length = Reflect::get(value, &JsValue::from_str("byteLength"))?;
+        let view = Uint8Array::new_with_byte_offset_and_length(
+            &buffer,
+            offset.as_f64().unwrap_or_default() as u32,
+            length.as_f64().unwrap_or_default() as u32,
+        );
+        Ok(Uint8Array::new(&view))
+    } else {
+        Err(js_sys::TypeError::new(&format!(
+            "Async iterator chunks should be strings, ArrayBuffers, or ArrayBufferViews, not {}",
+            type_name(value)
+        ))
+        .into())
+    }
+}
+
+/// A human-friendly name for a JavaScript value's type.
+fn type_name(value: &JsValue) -> String {
+    if value.is_null() {
+        return "null".to_string();
+    }
+
+    if value.is_object() {
+        let name = Reflect::get(value, &JsValue::from_str("constructor"))
+            .ok()
+            .and_then(|c| c.dyn_into::<js_sys::Function>().ok())
+            .and_then(|c| c.name().as_string())
+            .filter(|name| !name.is_empty());
+        if let Some(name) = name {
+            return name;
+        }
+    }
+
+    value.js_typeof().as_string().unwrap_or_default()
+}
+
 pub(crate) fn read_to_end(stream: ReadableStream) -> impl Stream<Item = Result<Vec<u8>, Error>> {
+    read_chunks(stream).map(|chunk| chunk.map(|c| Uint8Array::new(&c).to_vec()))
+}
+
+/// Read the tagged chunks from a [`merged_output_pipe()`]'s stream.
+pub(crate) fn read_merged_output(
+    stream: ReadableStream,
+) -> impl Stream<Item = Result<(OutputKind, Vec<u8>), Error>> {
+    read_chunks(stream).map(|chunk| {
+        let chunk = chunk?;
+        let kind = Reflect::get(&chunk, &"stream".into()).map_err(Error::js)?;
+        let kind = match kind.as_string().as_deref() {
+            Some("stderr") => OutputKind::Stderr,
+            _ => OutputKind::Stdout,
+        };
+        let data = Reflect::get(&chunk, &"data".into()).map_err(Error::js)?;
+
+        Ok((kind, Uint8Array::new(&data).to_vec()))
+    })
+}
+
+fn read_chunks(stream: ReadableStream) -> impl Stream<Item = Result<JsValue, Error>> {
     let reader = match ReadableStreamDefaultReader::new(&stream) {
         Ok(reader) => reader,
         Err(_) => {
@@ -255,7 +1099,7 @@ pub(crate) fn read_to_end(stream: ReadableStream) -> impl Stream<Item = Result<V
     Either::Right(stream)
 }
 
-fn get_chunk(next_chunk: JsValue) -> Result<Option<Vec<u8>>, Error> {
+fn get_chunk(next_chunk: JsValue) -> Result<Option<JsValue>, Error> {
     let done = JsValue::from_str("done");
     let value = JsValue::from_str("value");
 
@@ -265,14 +1109,13 @@ fn get_chunk(next_chunk: JsValue) -> Result<Option<Vec<u8>>, Error> {
     }
 
     let chunk = Reflect::get(&next_chunk, &value).map_err(Error::js)?;
-    let chunk = Uint8Array::new(&chunk);
 
-    Ok(Some(chunk.to_vec()))
+    Ok(Some(chunk))
 }
 
 #[cfg(test)]
 mod tests {
-    use futures::TryStreamExt;
+    use futures::{FutureExt, TryStreamExt};
     use wasm_bindgen_futures::JsFuture;
     use wasm_bindgen_test::wasm_bindgen_test;
 
@@ -327,6 +1170,23 @@ mod tests {
         drop(pipe);
     }
 
+    #[wasm_bindgen_test]
+    async fn merged_output_waits_for_js_to_catch_up() {
+        let (mut stdout, _stderr, stream) = merged_output_pipe();
+
+        // Keep writing until the channel is full
+        let mut writes = 0;
+        while stdout.write_all(b"x").now_or_never().is_some() {
+            writes += 1;
+            assert!(writes <= 2 * MERGED_OUTPUT_BACKLOG, "Writes never blocked");
+        }
+
+        // Reading a chunk from JS should make room for another write
+        let reader: ReadableStreamDefaultReader = stream.get_reader().unchecked_into();
+        JsFuture::from(reader.read()).await.unwrap();
+        stdout.write_all(b"x").await.unwrap();
+    }
+
     #[wasm_bindgen_test]
     async fn data_written_by_js_is_readable_from_the_pipe() {
         let (mut pipe, stream) = input_pipe();
@@ -343,4 +1203,53 @@ mod tests {
 
         assert_eq!(data, "Hello, World!");
     }
+
+    fn async_iterator(body: &str) -> AsyncIterator {
+        let generator =
+            js_sys::Function::new_no_args(&format!("return (async function* () {{ {body} }})();"));
+        generator
+            .call0(&JsValue::UNDEFINED)
+            .unwrap()
+            .unchecked_into()
+    }
+
+    #[wasm_bindgen_test]
+    async fn async_iterators_can_yield_strings_and_binary_data() {
+        let iterator = async_iterator(
+            r#"
+            yield "a";
+            yield new TextEncoder().encode("b");
+            yield new TextEncoder().encode("c").buffer;
+            yield new Uint16Array([0x6564]);
+            yield new DataView(new TextEncoder().encode("xfx").buffer, 1, 1);
+            "#,
+        );
+        let stream = async_iterator_stream(iterator);
+
+        let data = read_to_end(stream)
+            .try_fold(Vec::new(), |mut buffer, chunk| async {
+                buffer.extend(chunk);
+                Ok(buffer)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(String::from_utf8(data).unwrap(), "abcdef");
+    }
+
+    #[wasm_bindgen_test]
+    async fn async_iterators_cant_yield_numbers() {
+        let iterator = async_iterator("yield 42;");
+        let stream = async_iterator_stream(iterator);
+
+        let mut chunks = Box::pin(read_to_end(stream));
+        let err = chunks.try_next().await.unwrap_err();
+
+        let Error::JavaScript(err) = err else {
+            panic!("Expected a JavaScript error, found {err:?}");
+        };
+        let err: js_sys::Error = err.dyn_into().unwrap();
+        assert_eq!(err.name(), "TypeError");
+        assert!(String::from(err.message()).ends_with("not number"));
+    }
 }
@@ -26,6 +26,8 @@ impl PostMessagePayload {
 #[derivative(Debug)]
 pub(crate) enum BlockingJob {
     Thunk(#[derivative(Debug(format_with = "crate::utils::hidden"))] BlockingTask),
+    /// A long-lived `async` task which needs a worker to itself.
+    AsyncThunk(#[derivative(Debug(format_with = "crate::utils::hidden"))] AsyncTask),
     SpawnWithModule {
         module: WebAssembly::Module,
         #[derivative(Debug(format_with = "crate::utils::hidden"))]
@@ -58,6 +60,7 @@ pub(crate) enum Notification {
 mod consts {
     pub(crate) const TYPE_SPAWN_ASYNC: &str = "spawn-async";
     pub(crate) const TYPE_SPAWN_BLOCKING: &str = "spawn-blocking";
+    pub(crate) const TYPE_SPAWN_DEDICATED_ASYNC: &str = "spawn-dedicated-async";
     pub(crate) const TYPE_CACHE_MODULE: &str = "cache-module";
     pub(crate) const TYPE_SPAWN_WITH_MODULE: &str = "spawn-with-module";
     pub(crate) const TYPE_SPAWN_WITH_MODULE_AND_MEMORY: &str = "spawn-with-module-and-memory";
@@ -80,6 +83,11 @@ impl PostMessagePayload {
                     .boxed(consts::PTR, task)
                     .finish()
             }
+            PostMessagePayload::Blocking(BlockingJob::AsyncThunk(task)) => {
+                Serializer::new(consts::TYPE_SPAWN_DEDICATED_ASYNC)
+                    .boxed(consts::PTR, task)
+                    .finish()
+            }
             PostMessagePayload::Blocking(BlockingJob::SpawnWithModule { module, task }) => {
                 Serializer::new(consts::TYPE_SPAWN_WITH_MODULE)
                     .boxed(consts::PTR, task)
@@ -123,6 +131,10 @@ impl PostMessagePayload {
                 let task = de.boxed(consts::PTR)?;
                 Ok(PostMessagePayload::Blocking(BlockingJob::Thunk(task)))
             }
+            consts::TYPE_SPAWN_DEDICATED_ASYNC => {
+                let task = de.boxed(consts::PTR)?;
+                Ok(PostMessagePayload::Blocking(BlockingJob::AsyncThunk(task)))
+            }
             consts::TYPE_CACHE_MODULE => {
                 let module = de.js(consts::MODULE)?;
                 let hash = de.string(consts::MODULE_HASH)?;
@@ -134,7 +134,12 @@ impl SchedulerState {
             SchedulerMessage::SpawnBlocking(task) => {
                 self.post_message(PostMessagePayload::Blocking(BlockingJob::Thunk(task)))
             }
+            SchedulerMessage::SpawnDedicatedAsync(task) => {
+                self.post_message(PostMessagePayload::Blocking(BlockingJob::AsyncThunk(task)))
+            }
             SchedulerMessage::CacheModule { hash, module } => {
+                crate::module_cache::cache_locally(hash, &module);
+
                 let module: js_sys::WebAssembly::Module = JsValue::from(module).unchecked_into();
                 self.cached_modules.insert(hash, module.clone());
 
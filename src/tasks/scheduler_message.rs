@@ -24,13 +24,15 @@ pub(crate) enum SchedulerMessage {
     SpawnAsync(#[derivative(Debug(format_with = "crate::utils::hidden"))] AsyncTask),
     /// Run a blocking operation on a worker thread.
     SpawnBlocking(#[derivative(Debug(format_with = "crate::utils::hidden"))] BlockingTask),
+    /// Run a long-lived promise on a worker thread that won't be given any
+    /// other work until it completes.
+    SpawnDedicatedAsync(#[derivative(Debug(format_with = "crate::utils::hidden"))] AsyncTask),
     /// A message sent from a worker thread.
     /// Mark a worker as idle.
     WorkerIdle { worker_id: u32 },
     /// Mark a worker as busy.
     WorkerBusy { worker_id: u32 },
     /// Tell all workers to cache a WebAssembly module.
-    #[allow(dead_code)]
     CacheModule {
         hash: ModuleHash,
         module: wasmer::Module,
@@ -72,6 +74,10 @@ impl SchedulerMessage {
                 let task = de.boxed(consts::PTR)?;
                 Ok(SchedulerMessage::SpawnBlocking(task))
             }
+            consts::TYPE_SPAWN_DEDICATED_ASYNC => {
+                let task = de.boxed(consts::PTR)?;
+                Ok(SchedulerMessage::SpawnDedicatedAsync(task))
+            }
             consts::TYPE_WORKER_IDLE => {
                 let worker_id = de.serde(consts::WORKER_ID)?;
                 Ok(SchedulerMessage::WorkerIdle { worker_id })
@@ -133,6 +139,11 @@ impl SchedulerMessage {
             SchedulerMessage::SpawnBlocking(task) => Serializer::new(consts::TYPE_SPAWN_BLOCKING)
                 .boxed(consts::PTR, task)
                 .finish(),
+            SchedulerMessage::SpawnDedicatedAsync(task) => {
+                Serializer::new(consts::TYPE_SPAWN_DEDICATED_ASYNC)
+                    .boxed(consts::PTR, task)
+                    .finish()
+            }
             SchedulerMessage::WorkerIdle { worker_id } => Serializer::new(consts::TYPE_WORKER_IDLE)
                 .set(consts::WORKER_ID, worker_id)
                 .finish(),
@@ -175,6 +186,7 @@ impl SchedulerMessage {
 mod consts {
     pub const TYPE_SPAWN_ASYNC: &str = "spawn-async";
     pub const TYPE_SPAWN_BLOCKING: &str = "spawn-blocking";
+    pub const TYPE_SPAWN_DEDICATED_ASYNC: &str = "spawn-dedicated-async";
     pub const TYPE_WORKER_IDLE: &str = "worker-idle";
     pub const TYPE_WORKER_BUSY: &str = "worker-busy";
     pub const TYPE_CACHE_MODULE: &str = "cache-module";
@@ -32,6 +32,20 @@ impl ThreadPool {
         Ok(())
     }
 
+    /// Run a long-lived `async` function on a worker of its own.
+    ///
+    /// Unlike [`ThreadPool::spawn()`], the worker won't be given any other
+    /// work until the task completes, so it is safe for other threads to
+    /// block while waiting on it.
+    pub(crate) fn spawn_dedicated(
+        &self,
+        task: Box<dyn FnOnce() -> LocalBoxFuture<'static, ()> + Send>,
+    ) -> Result<(), WasiThreadError> {
+        self.send(SchedulerMessage::SpawnDedicatedAsync(task));
+
+        Ok(())
+    }
+
     pub(crate) fn send(&self, msg: SchedulerMessage) {
         self.scheduler.send(msg).expect("scheduler is dead");
     }
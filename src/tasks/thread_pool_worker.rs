@@ -33,8 +33,9 @@ impl ThreadPoolWorker {
         match msg {
             PostMessagePayload::Async(async_job) => self.execute_async(async_job).await,
             PostMessagePayload::Blocking(blocking) => self.execute_blocking(blocking).await,
-            PostMessagePayload::Notification(Notification::CacheModule { hash, module: _ }) => {
-                tracing::warn!(%hash, "TODO Caching module");
+            PostMessagePayload::Notification(Notification::CacheModule { hash, module }) => {
+                tracing::debug!(%hash, "Caching module");
+                crate::module_cache::cache_locally(hash, &module.into());
 
                 Ok(())
             }
@@ -57,6 +58,12 @@ impl ThreadPoolWorker {
                 let _guard = self.busy();
                 thunk();
             }
+            BlockingJob::AsyncThunk(thunk) => {
+                // Note: The worker stays busy until the task completes so the
+                // scheduler doesn't give it any blocking work in the meantime.
+                let _guard = self.busy();
+                thunk().await;
+            }
             BlockingJob::SpawnWithModule { module, task } => {
                 let _guard = self.busy();
                 task(module.into());
@@ -0,0 +1,333 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use bytes::BytesMut;
+use tracing::Instrument;
+use virtual_fs::{AsyncReadExt, Pipe, VirtualFile};
+use wasmer_wasix::{
+    os::{Tty, TtyBridge, TtyOptions},
+    WasiTtyState,
+};
+use web_sys::WritableStream;
+
+use crate::{options::Stdin, streams::SharedFile, utils::GlobalScope};
+
+/// The terminal state belonging to a single instance.
+///
+/// Each running program gets its own [`TtyState`] so that things like
+/// toggling echo or switching out of line-buffered mode in an interactive
+/// shell don't leak into other programs running on the same
+/// [`Runtime`][crate::runtime::Runtime].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TtyState {
+    options: TtyOptions,
+    connected: Arc<AtomicBool>,
+    pixels: Arc<Mutex<PixelSize>>,
+}
+
+/// The terminal's dimensions in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct PixelSize {
+    width: u32,
+    height: u32,
+}
+
+impl Default for PixelSize {
+    fn default() -> Self {
+        PixelSize {
+            width: 800,
+            height: 600,
+        }
+    }
+}
+
+impl TtyState {
+    /// The options shared with the [`Tty`][wasmer_wasix::os::Tty] that
+    /// processes this instance's input.
+    pub(crate) fn options(&self) -> &TtyOptions {
+        &self.options
+    }
+
+    /// Should stdin, stdout, and stderr be reported as a terminal?
+    pub(crate) fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::SeqCst);
+    }
+
+    /// Update the terminal's dimensions, leaving the size in pixels as-is
+    /// if it isn't provided.
+    pub(crate) fn resize(&self, cols: u32, rows: u32, width: Option<u32>, height: Option<u32>) {
+        self.options.set_cols(cols);
+        self.options.set_rows(rows);
+
+        let mut pixels = self.pixels.lock().unwrap();
+        if let Some(width) = width {
+            pixels.width = width;
+        }
+        if let Some(height) = height {
+            pixels.height = height;
+        }
+    }
+
+    fn connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+}
+
+impl TtyBridge for TtyState {
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn reset(&self) {
+        self.options.set_echo(true);
+        self.options.set_line_buffering(true);
+        self.options.set_line_feeds(true);
+        self.set_connected(false);
+    }
+
+    #[tracing::instrument(level = "debug", skip(self), ret)]
+    fn tty_get(&self) -> WasiTtyState {
+        let connected = self.connected();
+        let PixelSize { width, height } = *self.pixels.lock().unwrap();
+
+        WasiTtyState {
+            cols: self.options.cols(),
+            rows: self.options.rows(),
+            width,
+            height,
+            stdin_tty: connected,
+            stdout_tty: connected,
+            stderr_tty: connected,
+            echo: self.options.echo(),
+            line_buffered: self.options.line_buffering(),
+            line_feeds: self.options.line_feeds(),
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip(self))]
+    fn tty_set(&self, tty_state: WasiTtyState) {
+        self.options.set_cols(tty_state.cols);
+        self.options.set_rows(tty_state.rows);
+        *self.pixels.lock().unwrap() = PixelSize {
+            width: tty_state.width,
+            height: tty_state.height,
+        };
+        self.options.set_echo(tty_state.echo);
+        self.options.set_line_buffering(tty_state.line_buffered);
+        self.options.set_line_feeds(tty_state.line_feeds);
+        self.set_connected(tty_state.stdin_tty || tty_state.stdout_tty || tty_state.stderr_tty);
+    }
+}
+
+/// Explicit terminal settings provided by the user.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct TtySettings {
+    pub cols: Option<u32>,
+    pub rows: Option<u32>,
+    pub echo: Option<bool>,
+    pub line_buffered: Option<bool>,
+}
+
+impl TtySettings {
+    /// Override the corresponding parts of a [`TtyState`].
+    pub(crate) fn apply(&self, tty: &TtyState) {
+        if let Some(cols) = self.cols {
+            tty.options.set_cols(cols);
+        }
+        if let Some(rows) = self.rows {
+            tty.options.set_rows(rows);
+        }
+        if let Some(echo) = self.echo {
+            tty.options.set_echo(echo);
+        }
+        if let Some(line_buffered) = self.line_buffered {
+            tty.options.set_line_buffering(line_buffered);
+        }
+    }
+}
+
+/// The stdin and stdout a program should use once its terminal has been set
+/// up.
+#[derive(Debug)]
+pub(crate) struct Terminal {
+    /// The file to use as the program's stdin.
+    pub stdin: Box<dyn VirtualFile + Send + Sync>,
+    /// The file to use as the program's stdout.
+    pub stdout: Box<dyn VirtualFile + Send + Sync>,
+    /// The [`WritableStream`] our JavaScript caller will write stdin to, if
+    /// stdin wasn't provided up front.
+    pub stdin_stream: Option<WritableStream>,
+}
+
+/// Wire up a program's stdin and stdout, passing stdin through a [`Tty`] if
+/// the session is `interactive`.
+pub(crate) fn connect(
+    tty: &TtyState,
+    interactive: bool,
+    stdin: Option<Stdin>,
+    stdout: Box<dyn VirtualFile + Send + Sync>,
+) -> Terminal {
+    tty.set_connected(interactive);
+
+    let (stdin, stdin_stream) = match stdin {
+        Some(stdin) => (stdin.into_file(), None),
+        None => {
+            let (pipe, stream) = crate::streams::input_pipe();
+            (
+                Box::new(pipe) as Box<dyn VirtualFile + Send + Sync>,
+                Some(stream),
+            )
+        }
+    };
+
+    if !interactive {
+        return Terminal {
+            stdin,
+            stdout,
+            stdin_stream,
+        };
+    }
+
+    // Note: The TTY echoes input back to stdout, so it needs to share the
+    // program's stdout.
+    let stdout = SharedFile::new(stdout);
+
+    // Note: Because this is an interactive session, we want to intercept
+    // stdin and let the TTY modify it.
+    //
+    // To do that, we manually copy data from the user's stdin into the TTY,
+    // then the TTY modifies those bytes and writes them to the pipe we gave
+    // to the runtime.
+    //
+    // To avoid confusing the pipes and how stdin data gets moved around,
+    // here's a diagram:
+    //
+    //  ----------------------            --------------------          ----------------------------
+    // | stdin (user) u_stdin | --copy--> | (tty) u_stdin_tx  | --pipe-> | stdin_pipe (runtime) ... |
+    //  ----------------------            --------------------          ----------------------------
+    let (u_stdin_tx, stdin_pipe) = Pipe::channel();
+
+    let processor = Tty::new(
+        Box::new(u_stdin_tx),
+        Box::new(stdout.clone()),
+        GlobalScope::current().is_mobile(),
+        tty.options().clone(),
+    );
+
+    // Because the TTY is manually copying between pipes, we need to make
+    // sure the stdin pipe passed to the runtime is closed when the user
+    // closes their end.
+    let cleanup = {
+        let stdin_pipe = stdin_pipe.clone();
+        move || {
+            tracing::debug!("Closing stdin");
+            stdin_pipe.close();
+        }
+    };
+
+    // Use the JS event loop to drive our manual user->tty copy
+    wasm_bindgen_futures::spawn_local(
+        copy_stdin_to_tty(stdin, processor, cleanup)
+            .in_current_span()
+            .instrument(tracing::debug_span!("tty")),
+    );
+
+    Terminal {
+        stdin: Box::new(stdin_pipe),
+        stdout: Box::new(stdout),
+        stdin_stream,
+    }
+}
+
+fn copy_stdin_to_tty(
+    mut u_stdin: Box<dyn VirtualFile + Send + Sync>,
+    mut tty: Tty,
+    cleanup: impl FnOnce(),
+) -> impl std::future::Future<Output = ()> {
+    /// A RAII guard used to make sure the cleanup function always gets called.
+    struct CleanupGuard<F: FnOnce()>(Option<F>);
+
+    impl<F: FnOnce()> Drop for CleanupGuard<F> {
+        fn drop(&mut self) {
+            let cb = self.0.take().unwrap();
+            cb();
+        }
+    }
+
+    async move {
+        let _guard = CleanupGuard(Some(cleanup));
+        let mut buffer = BytesMut::new();
+
+        loop {
+            match u_stdin.read_buf(&mut buffer).await {
+                Ok(0) => {
+                    break;
+                }
+                Ok(_) => {
+                    // PERF: It'd be nice if we didn't need to do a copy here.
+                    let data = buffer.to_vec();
+                    tty = tty.on_event(wasmer_wasix::os::InputEvent::Raw(data)).await;
+                    buffer.clear();
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        error = &e as &dyn std::error::Error,
+                        "Error reading stdin and copying it to the tty"
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn tty_state_is_not_shared_between_instances() {
+        let first = TtyState::default();
+        let second = TtyState::default();
+
+        first.set_connected(true);
+        first.options().set_echo(false);
+
+        assert!(first.tty_get().stdin_tty);
+        assert!(!first.tty_get().echo);
+        assert!(!second.tty_get().stdin_tty);
+        assert!(second.tty_get().echo);
+    }
+
+    #[wasm_bindgen_test]
+    fn resizing_keeps_the_previous_pixel_size_by_default() {
+        let tty = TtyState::default();
+
+        tty.resize(120, 40, Some(1024), None);
+
+        let state = tty.tty_get();
+        assert_eq!(state.cols, 120);
+        assert_eq!(state.rows, 40);
+        assert_eq!(state.width, 1024);
+        assert_eq!(state.height, 600);
+    }
+
+    #[wasm_bindgen_test]
+    fn settings_only_override_what_was_provided() {
+        let tty = TtyState::default();
+        let settings = TtySettings {
+            cols: Some(132),
+            echo: Some(false),
+            ..Default::default()
+        };
+
+        settings.apply(&tty);
+
+        let state = tty.tty_get();
+        assert_eq!(state.cols, 132);
+        assert_eq!(state.rows, TtyOptions::default().rows());
+        assert!(!state.echo);
+        assert!(state.line_buffered);
+    }
+}
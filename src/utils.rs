@@ -7,7 +7,7 @@ use std::{
 use js_sys::{JsString, Promise, Uint8Array};
 
 use wasm_bindgen::{prelude::wasm_bindgen, JsCast, JsValue};
-use web_sys::{Window, WorkerGlobalScope};
+use web_sys::{CacheStorage, IdbFactory, StorageManager, Window, WorkerGlobalScope};
 
 /// Try to extract the most appropriate error message from a [`JsValue`],
 /// falling back to a generic error message.
@@ -85,6 +85,35 @@ impl GlobalScope {
         NonZeroUsize::new(concurrency)
     }
 
+    /// The [`StorageManager`] used to access things like the Origin Private
+    /// File System.
+    pub fn storage(&self) -> Option<StorageManager> {
+        match self {
+            GlobalScope::Window(scope) => Some(scope.navigator().storage()),
+            GlobalScope::Worker(scope) => Some(scope.navigator().storage()),
+            GlobalScope::Other(_) => None,
+        }
+    }
+
+    /// The [`CacheStorage`] used for storing HTTP responses across page
+    /// loads.
+    pub fn caches(&self) -> Option<CacheStorage> {
+        match self {
+            GlobalScope::Window(scope) => scope.caches().ok(),
+            GlobalScope::Worker(scope) => scope.caches().ok(),
+            GlobalScope::Other(_) => None,
+        }
+    }
+
+    /// The [`IdbFactory`] used to access IndexedDB.
+    pub fn indexed_db(&self) -> Option<IdbFactory> {
+        match self {
+            GlobalScope::Window(scope) => scope.indexed_db().ok().flatten(),
+            GlobalScope::Worker(scope) => scope.indexed_db().ok().flatten(),
+            GlobalScope::Other(_) => None,
+        }
+    }
+
     pub fn is_mobile(&self) -> bool {
         match self.user_agent() {
             Some(user_agent) => wasmer_wasix::os::common::is_mobile(&user_agent),
@@ -139,33 +168,44 @@ impl From<Error> for JsValue {
     fn from(error: Error) -> Self {
         match error {
             Error::JavaScript(e) => e,
-            Error::Rust(error) => {
-                let message = error.to_string();
-                let js_error = js_sys::Error::new(&message);
-
-                let _ = js_sys::Reflect::set(
-                    &js_error,
-                    &JsString::from("message"),
-                    &JsString::from(error.to_string()),
-                );
-
-                let _ = js_sys::Reflect::set(
-                    &js_error,
-                    &JsString::from("detailedMessage"),
-                    &JsString::from(format!("{error:?}")),
-                );
-
-                let causes: js_sys::Array = std::iter::successors(error.source(), |e| e.source())
-                    .map(|e| JsString::from(e.to_string()))
-                    .collect();
-                let _ = js_sys::Reflect::set(&js_error, &JsString::from("causes"), &causes);
-
-                js_error.into()
-            }
+            Error::Rust(error) => anyhow_to_js(&error).into(),
         }
     }
 }
 
+/// Convert an [`anyhow::Error`] into a JavaScript `Error` with extra
+/// `detailedMessage` and `causes` properties.
+pub(crate) fn anyhow_to_js(error: &anyhow::Error) -> js_sys::Error {
+    let message = error.to_string();
+    let js_error = js_sys::Error::new(&message);
+
+    let _ = js_sys::Reflect::set(
+        &js_error,
+        &JsString::from("message"),
+        &JsString::from(error.to_string()),
+    );
+
+    let _ = js_sys::Reflect::set(
+        &js_error,
+        &JsString::from("detailedMessage"),
+        &JsString::from(format!("{error:?}")),
+    );
+
+    let causes: js_sys::Array = std::iter::successors(error.source(), |e| e.source())
+        .map(|e| JsString::from(e.to_string()))
+        .collect();
+    let _ = js_sys::Reflect::set(&js_error, &JsString::from("causes"), &causes);
+
+    if let Some(integrity) = error
+        .chain()
+        .find_map(|e| e.downcast_ref::<crate::package_loader::IntegrityError>())
+    {
+        integrity.annotate(&js_error);
+    }
+
+    js_error
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -268,6 +308,28 @@ pub(crate) fn js_record_of_strings(obj: &js_sys::Object) -> Result<Vec<(String,
     Ok(parsed)
 }
 
+/// Encode some bytes as base64.
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    // Note: btoa() works with "binary strings", where each character is a
+    // single byte.
+    let binary: String = bytes.iter().map(|&b| char::from(b)).collect();
+    btoa(&binary)
+}
+
+/// Decode a base64 string, returning [`None`] if it is invalid.
+pub(crate) fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let binary = atob(encoded).ok()?;
+    Some(binary.chars().map(|c| c as u8).collect())
+}
+
+#[wasm_bindgen]
+extern "C" {
+    fn btoa(data: &str) -> String;
+
+    #[wasm_bindgen(catch)]
+    fn atob(data: &str) -> Result<String, JsValue>;
+}
+
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(typescript_type = "string | Uint8Array")]
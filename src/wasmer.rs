@@ -1,25 +1,27 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
-use bytes::BytesMut;
-use futures::{channel::oneshot, TryStreamExt};
+use anyhow::Context;
+use futures::{channel::oneshot, FutureExt, TryStreamExt};
+use http::{HeaderMap, HeaderName, HeaderValue};
 use js_sys::{JsString, Reflect, Uint8Array};
-use tracing::Instrument;
-use virtual_fs::{AsyncReadExt, Pipe};
-use wasm_bindgen::{prelude::wasm_bindgen, JsValue, UnwrapThrowExt};
+use url::Url;
+use virtual_fs::FileSystem;
+use wasm_bindgen::{prelude::wasm_bindgen, JsCast, JsValue, UnwrapThrowExt};
 use wasmer_wasix::{
     bin_factory::BinaryPackage,
-    os::{Tty, TtyOptions},
     runners::{wasi::WasiRunner, Runner},
-    runtime::resolver::PackageSpecifier,
-    Runtime as _,
+    runtime::resolver::{PackageSpecifier, WebcHash},
+    Runtime as _, WasiEnvBuilder,
 };
-use web_sys::{ReadableStream, WritableStream};
+use webc::metadata::annotations::Wasi;
 
 use crate::{
-    instance::ExitCondition,
+    instance::{run_with_process_handle, ExitCondition, ProcessHandle},
+    options::TtyMode,
     runtime::Runtime,
-    utils::{Error, GlobalScope},
-    Instance, JsRuntime, SpawnOptions,
+    tty::TtyState,
+    utils::Error,
+    Directory, Instance, JsRuntime, SpawnOptions,
 };
 
 /// A package from the Wasmer registry.
@@ -69,6 +71,43 @@ impl Wasmer {
     ) -> Result<Wasmer, Error> {
         Wasmer::from_file(binary.to_vec(), runtime).await
     }
+
+    /// Download a package from a URL.
+    ///
+    /// The package's dependencies will be looked up using the runtime's
+    /// registry.
+    #[wasm_bindgen(js_name = "fromUrl")]
+    pub async fn js_from_url(
+        url: &str,
+        options: Option<FromUrlOptions>,
+        runtime: Option<OptionalRuntime>,
+    ) -> Result<Wasmer, Error> {
+        let (headers, integrity) = match &options {
+            Some(options) => (options.parse_headers()?, options.parse_integrity()?),
+            None => (HeaderMap::new(), None),
+        };
+
+        Wasmer::from_url(url, headers, integrity, runtime).await
+    }
+
+    /// Build a package from a {@link Directory} containing a `wasmer.toml`
+    /// file, plus the modules and directories it refers to.
+    ///
+    /// The package's dependencies will be looked up using the runtime's
+    /// registry.
+    #[wasm_bindgen(js_name = "fromDirectory")]
+    pub async fn js_from_directory(
+        directory: DirectoryArg,
+        runtime: Option<OptionalRuntime>,
+    ) -> Result<Wasmer, Error> {
+        let directory = Directory::try_from(&JsValue::from(directory)).map_err(|_| {
+            Error::js(js_sys::TypeError::new(
+                "Expected a Directory containing a wasmer.toml file",
+            ))
+        })?;
+
+        Wasmer::from_directory(directory, runtime).await
+    }
 }
 
 /// The actual impl - with `#[tracing::instrument]` macros.
@@ -94,6 +133,41 @@ impl Wasmer {
         Wasmer::from_package(pkg, runtime)
     }
 
+    #[tracing::instrument(skip(headers, runtime))]
+    async fn from_url(
+        url: &str,
+        headers: HeaderMap,
+        integrity: Option<WebcHash>,
+        runtime: Option<OptionalRuntime>,
+    ) -> Result<Self, Error> {
+        let url: Url = url
+            .parse()
+            .with_context(|| format!("Unable to parse \"{url}\" as a URL"))?;
+        let runtime = runtime.unwrap_or_default().resolve()?.into_inner();
+
+        let webc = runtime
+            .package_downloader()
+            .download_url(&url, headers, integrity)
+            .await?;
+        let container = webc::Container::from_bytes(webc)?;
+        let pkg = BinaryPackage::from_webc(&container, &*runtime).await?;
+
+        Wasmer::from_package(pkg, runtime)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn from_directory(
+        directory: Directory,
+        runtime: Option<OptionalRuntime>,
+    ) -> Result<Self, Error> {
+        let runtime = runtime.unwrap_or_default().resolve()?.into_inner();
+        let webc = crate::manifest::build_webc(&directory).await?;
+        let container = webc::Container::from_bytes(webc)?;
+        let pkg = BinaryPackage::from_webc(&container, &*runtime).await?;
+
+        Wasmer::from_package(pkg, runtime)
+    }
+
     fn from_package(pkg: BinaryPackage, runtime: Arc<Runtime>) -> Result<Self, Error> {
         let pkg = Arc::new(pkg);
         let commands = Commands::default();
@@ -134,33 +208,64 @@ pub struct Command {
 #[wasm_bindgen]
 impl Command {
     pub async fn run(&self, options: Option<SpawnOptions>) -> Result<Instance, Error> {
-        let runtime = Arc::clone(&self.runtime);
+        // Note: Every instance gets its own TTY so programs running
+        // concurrently on the same runtime don't interfere with each other.
+        let tty = TtyState::default();
+        let runtime = Arc::new(self.runtime.with_tty(tty.clone()));
         let pkg = Arc::clone(&self.pkg);
         let tasks = Arc::clone(runtime.task_manager());
 
         let options = options.unwrap_or_default();
 
         let mut runner = WasiRunner::new();
-        let (stdin, stdout, stderr) = configure_runner(&options, &mut runner, &runtime).await?;
+        let (stdin, stdout, stderr, output) =
+            configure_runner(&options, &mut runner, &runtime, &tty).await?;
         let command_name = String::from(&self.name);
+        let cwd = options.parse_cwd();
 
         tracing::debug!(%command_name, "Starting the WASI runner");
 
         let (sender, receiver) = oneshot::channel();
-
-        // Note: The WasiRunner::run_command() method blocks, so we need to run
-        // it on the thread pool.
-        tasks.task_dedicated(Box::new(move || {
-            let result = runner.run_command(&command_name, &pkg, runtime);
-            let _ = sender.send(ExitCondition::from_result(result));
+        let process = ProcessHandle::default();
+        let timeout = options.parse_timeout()?;
+
+        // Note: Running the command blocks, so we need to run it on the
+        // thread pool.
+        tasks.task_dedicated(Box::new({
+            let process = process.clone();
+            move || {
+                let prepared = prepare_command(&runner, &command_name, &pkg, &runtime);
+                let exit_condition = match prepared {
+                    Ok((mut builder, module)) => {
+                        if let Some(cwd) = cwd {
+                            builder.set_current_dir(cwd);
+                        }
+
+                        run_with_process_handle(builder, module, &*runtime, &process)
+                    }
+                    Err(e) => ExitCondition::from_result(Err(e)),
+                };
+                let _ = sender.send(exit_condition);
+            }
         }))?;
 
-        Ok(Instance {
+        if let Some(timeout) = timeout {
+            process.kill_after(timeout);
+        }
+
+        let mut instance = Instance {
             stdin,
             stdout,
             stderr,
-            exit: receiver,
-        })
+            output,
+            exit: receiver.shared(),
+            process,
+            tty,
+            forwarded: None,
+        };
+        instance.forward_output(options.on_stdout(), options.on_stderr());
+
+        Ok(instance)
     }
 
     /// Read the binary that will be
@@ -180,6 +285,10 @@ extern "C" {
     /// A helper to allow functions to take a `runtime?: Runtime` parameter.
     #[wasm_bindgen(typescript_type = "Runtime")]
     pub type OptionalRuntime;
+
+    /// A helper to allow async functions to borrow a {@link Directory}.
+    #[wasm_bindgen(typescript_type = "Directory")]
+    pub type DirectoryArg;
 }
 
 impl OptionalRuntime {
@@ -203,15 +312,130 @@ impl Default for OptionalRuntime {
     }
 }
 
+#[wasm_bindgen(typescript_custom_section)]
+const FROM_URL_OPTIONS_TYPE_DECLARATION: &str = r#"
+/**
+ * Options used when downloading a package with {@link Wasmer.fromUrl}.
+ */
+export type FromUrlOptions = {
+    /**
+     * Extra headers to send when downloading the package (e.g. for
+     * authentication).
+     */
+    headers?: Record<string, string>;
+    /**
+     * The package's expected SHA-256 hash, as a
+     * [Subresource Integrity](https://developer.mozilla.org/en-US/docs/Web/Security/Subresource_Integrity)
+     * string (e.g. `"sha256-..."`).
+     *
+     * If provided, the download will fail with an {@link IntegrityError}
+     * when the package doesn't match, and a previously cached copy of the
+     * package may be used instead of downloading it again.
+     */
+    integrity?: string;
+};
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "FromUrlOptions")]
+    pub type FromUrlOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn headers(this: &FromUrlOptions) -> JsValue;
+
+    #[wasm_bindgen(method, getter)]
+    fn integrity(this: &FromUrlOptions) -> Option<String>;
+}
+
+impl FromUrlOptions {
+    fn parse_headers(&self) -> Result<HeaderMap, Error> {
+        let mut headers = HeaderMap::new();
+
+        if let Some(obj) = self.headers().dyn_ref() {
+            for (name, value) in crate::utils::js_record_of_strings(obj)? {
+                let invalid = || {
+                    Error::js(js_sys::TypeError::new(&format!(
+                        "Invalid header, \"{name}: {value}\""
+                    )))
+                };
+                let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+                let header_value = HeaderValue::from_str(&value).map_err(|_| invalid())?;
+                headers.append(header_name, header_value);
+            }
+        }
+
+        Ok(headers)
+    }
+
+    fn parse_integrity(&self) -> Result<Option<WebcHash>, Error> {
+        self.integrity().as_deref().map(parse_integrity).transpose()
+    }
+}
+
+/// Get the SHA-256 hash from a [Subresource Integrity][sri] string.
+///
+/// [sri]: https://developer.mozilla.org/en-US/docs/Web/Security/Subresource_Integrity
+fn parse_integrity(integrity: &str) -> Result<WebcHash, Error> {
+    let invalid = || {
+        Error::js(js_sys::TypeError::new(&format!(
+            "Expected a \"sha256-...\" integrity string, but found \"{integrity}\""
+        )))
+    };
+
+    // Note: There may be several space-separated hashes, each of which can
+    // be followed by options (e.g. "sha256-abcd?foo").
+    let digest = integrity
+        .split_whitespace()
+        .find_map(|hash| hash.strip_prefix("sha256-"))
+        .and_then(|digest| digest.split('?').next())
+        .ok_or_else(invalid)?;
+
+    let bytes = crate::utils::base64_decode(digest).ok_or_else(invalid)?;
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| invalid())?;
+
+    Ok(WebcHash::from_bytes(bytes))
+}
+
+/// Do everything [`WasiRunner::run_command()`] would do, except actually
+/// running the command, so the caller can keep track of the process it
+/// creates.
+fn prepare_command(
+    runner: &WasiRunner,
+    command_name: &str,
+    pkg: &BinaryPackage,
+    runtime: &Arc<Runtime>,
+) -> Result<(WasiEnvBuilder, wasmer::Module), anyhow::Error> {
+    let cmd = pkg
+        .get_command(command_name)
+        .with_context(|| format!("The package doesn't contain a \"{command_name}\" command"))?;
+    let wasi = cmd
+        .metadata()
+        .annotation("wasi")?
+        .unwrap_or_else(|| Wasi::new(command_name));
+
+    let builder = runner
+        .prepare_webc_env(command_name, &wasi, pkg, Arc::clone(runtime) as _, None)
+        .context("Unable to prepare the WASI environment")?;
+    // Note: We're on a worker thread, so it's okay to block while the module
+    // cache is checked.
+    let module = futures::executor::block_on(runtime.load_module(cmd.atom()))
+        .context("Unable to compile the command")?;
+
+    Ok((builder, module))
+}
+
 pub(crate) async fn configure_runner(
     options: &SpawnOptions,
     runner: &mut WasiRunner,
     runtime: &Runtime,
+    tty: &TtyState,
 ) -> Result<
     (
         Option<web_sys::WritableStream>,
         web_sys::ReadableStream,
         web_sys::ReadableStream,
+        Option<web_sys::ReadableStream>,
     ),
     Error,
 > {
@@ -221,7 +445,19 @@ pub(crate) async fn configure_runner(
     let env = options.parse_env()?;
     runner.set_envs(env);
 
-    for (dest, dir) in options.mounted_directories()? {
+    let mounted_directories = options.mounted_directories()?;
+
+    if let Some(cwd) = options.parse_cwd() {
+        let create = options.create_cwd().unwrap_or(false);
+        ensure_dir_in_mounts(&mounted_directories, &cwd, create).with_context(|| {
+            format!(
+                "Unable to use \"{}\" as the working directory",
+                cwd.display()
+            )
+        })?;
+    }
+
+    for (dest, dir) in mounted_directories {
         runner.mount(dest, dir);
     }
 
@@ -231,160 +467,59 @@ pub(crate) async fn configure_runner(
         runner.add_injected_packages(packages);
     }
 
-    let (stderr_pipe, stderr_stream) = crate::streams::output_pipe();
-    runner.set_stderr(Box::new(stderr_pipe));
-
-    let tty_options = runtime.tty_options().clone();
-    match setup_tty(options, tty_options) {
-        TerminalMode::Interactive {
-            stdin_pipe,
-            stdout_pipe,
-            stdout_stream,
-            stdin_stream,
-        } => {
-            tracing::debug!("Setting up interactive TTY");
-            runner.set_stdin(Box::new(stdin_pipe));
-            runner.set_stdout(Box::new(stdout_pipe));
-            runtime.set_connected_to_tty(true);
-            Ok((Some(stdin_stream), stdout_stream, stderr_stream))
-        }
-        TerminalMode::NonInteractive { stdin } => {
-            tracing::debug!("Setting up non-interactive TTY");
-            let (stdout_pipe, stdout_stream) = crate::streams::output_pipe();
-            runner.set_stdin(Box::new(stdin));
-            runner.set_stdout(Box::new(stdout_pipe));
-
-            // HACK: Make sure we don't report stdin as interactive.  This
-            // doesn't belong here because now it'll affect every other
-            // instance sharing the same runtime... In theory, every
-            // instance should get its own TTY state, but that's an issue
-            // for wasmer-wasix to work out.
-            runtime.set_connected_to_tty(false);
-
-            Ok((None, stdout_stream, stderr_stream))
-        }
-    }
-}
-
-fn setup_tty(options: &SpawnOptions, tty_options: TtyOptions) -> TerminalMode {
-    // Handle the simple (non-interactive) case first.
-    if let Some(stdin) = options.read_stdin() {
-        return TerminalMode::NonInteractive {
-            stdin: virtual_fs::StaticFile::new(stdin),
-        };
-    }
+    let outputs = options.output_targets()?;
+    runner.set_stderr(outputs.stderr);
 
-    let (stdout_pipe, stdout_stream) = crate::streams::output_pipe();
-
-    // Note: Because this is an interactive session, we want to intercept
-    // stdin and let the TTY modify it.
-    //
-    // To do that, we manually copy data from the user's pipe into the TTY,
-    // then the TTY modifies those bytes and writes them to the pipe we gave
-    // to the runtime.
-    //
-    // To avoid confusing the pipes and how stdin data gets moved around,
-    // here's a diagram:
-    //
-    //  ---------------------------------            --------------------          ----------------------------
-    // | stdin_stream (user) u_stdin_rx | --copy--> | (tty) u_stdin_tx  | --pipe-> | stdin_pipe (runtime) ... |
-    // ---------------------------------            --------------------          ----------------------------
-    let (u_stdin_rx, stdin_stream) = crate::streams::input_pipe();
-    let (u_stdin_tx, stdin_pipe) = Pipe::channel();
-
-    let tty = Tty::new(
-        Box::new(u_stdin_tx),
-        Box::new(stdout_pipe.clone()),
-        GlobalScope::current().is_mobile(),
-        tty_options,
-    );
-
-    // Because the TTY is manually copying between pipes, we need to make
-    // sure the stdin pipe passed to the runtime is closed when the user
-    // closes their end.
-    let cleanup = {
-        let stdin_pipe = stdin_pipe.clone();
-        move || {
-            tracing::debug!("Closing stdin");
-            stdin_pipe.close();
+    let stdin = options.parse_stdin()?;
+    let interactive = match options.parse_tty()? {
+        TtyMode::Inferred => stdin.is_none(),
+        TtyMode::Disabled => false,
+        TtyMode::Enabled(settings) => {
+            settings.apply(tty);
+            true
         }
     };
 
-    // Use the JS event loop to drive our manual user->tty copy
-    wasm_bindgen_futures::spawn_local(
-        copy_stdin_to_tty(u_stdin_rx, tty, cleanup)
-            .in_current_span()
-            .instrument(tracing::debug_span!("tty")),
-    );
-
-    TerminalMode::Interactive {
-        stdin_pipe,
-        stdout_pipe,
-        stdout_stream,
-        stdin_stream,
-    }
-}
+    let terminal = crate::tty::connect(tty, interactive, stdin, outputs.stdout);
+    runner.set_stdin(terminal.stdin);
+    runner.set_stdout(terminal.stdout);
 
-fn copy_stdin_to_tty(
-    mut u_stdin_rx: Pipe,
-    mut tty: Tty,
-    cleanup: impl FnOnce(),
-) -> impl std::future::Future<Output = ()> {
-    /// A RAII guard used to make sure the cleanup function always gets called.
-    struct CleanupGuard<F: FnOnce()>(Option<F>);
-
-    impl<F: FnOnce()> Drop for CleanupGuard<F> {
-        fn drop(&mut self) {
-            let cb = self.0.take().unwrap();
-            cb();
-        }
-    }
+    Ok((
+        terminal.stdin_stream,
+        outputs.stdout_stream,
+        outputs.stderr_stream,
+        outputs.output,
+    ))
+}
 
-    async move {
-        let _guard = CleanupGuard(Some(cleanup));
-        let mut buffer = BytesMut::new();
-
-        loop {
-            match u_stdin_rx.read_buf(&mut buffer).await {
-                Ok(0) => {
-                    break;
-                }
-                Ok(_) => {
-                    // PERF: It'd be nice if we didn't need to do a copy here.
-                    let data = buffer.to_vec();
-                    tty = tty.on_event(wasmer_wasix::os::InputEvent::Raw(data)).await;
-                    buffer.clear();
-                }
-                Err(e) => {
-                    tracing::warn!(
-                        error = &e as &dyn std::error::Error,
-                        "Error reading stdin and copying it to the tty"
-                    );
-                    break;
-                }
-            }
+/// Make sure a directory exists inside whichever mounted directory contains
+/// it, optionally creating it first.
+///
+/// Note: Paths outside the mounted directories may still refer to one of the
+/// package's own volumes, which aren't available until the command is
+/// prepared, so those can only be checked by the program itself.
+fn ensure_dir_in_mounts(
+    mounted_directories: &[(String, Arc<dyn FileSystem + Send + Sync>)],
+    path: &Path,
+    create: bool,
+) -> Result<(), anyhow::Error> {
+    let mount = mounted_directories
+        .iter()
+        .filter(|(dest, _)| path.starts_with(dest))
+        .max_by_key(|(dest, _)| dest.len());
+
+    match mount {
+        Some((dest, fs)) => {
+            let relative = path.strip_prefix(dest).unwrap_or(path);
+            crate::fs::ensure_dir(&**fs, &Path::new("/").join(relative), create)
         }
+        None if create => Err(anyhow::anyhow!(
+            "Only directories inside a mounted directory can be created"
+        )),
+        None => Ok(()),
     }
 }
 
-#[derive(Debug)]
-enum TerminalMode {
-    Interactive {
-        /// The [`Pipe`] used as the WASIX instance's stdin.
-        stdin_pipe: Pipe,
-        /// The [`Pipe`] used as the WASIX instance's stdout.
-        stdout_pipe: Pipe,
-        /// The [`ReadableStream`] our JavaScript caller will read stdout from.
-        stdout_stream: ReadableStream,
-        /// The [`WritableStream`] our JavaScript caller will write stdin to.
-        stdin_stream: WritableStream,
-    },
-    NonInteractive {
-        /// The file to use as the WASIX instance's stdin.
-        stdin: virtual_fs::StaticFile,
-    },
-}
-
 #[tracing::instrument(level = "debug", skip_all)]
 async fn load_injected_packages(
     packages: Vec<String>,
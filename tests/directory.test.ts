@@ -59,4 +59,465 @@ describe("In-Memory Directory", function () {
             "another",
         );
     });
+
+    it("can stat files and directories", async () => {
+        const dir = new Directory({ "/file.txt": "Hello, World!" });
+
+        const file = await dir.stat("/file.txt");
+        const root = await dir.stat("/");
+
+        expect(file.type).to.equal("file");
+        expect(file.size).to.equal(13);
+        expect(file.modified).to.be.greaterThan(0);
+        expect(root.type).to.equal("dir");
+    });
+
+    it("can check whether a path exists", async () => {
+        const dir = new Directory({ "/file.txt": "" });
+
+        expect(await dir.exists("/file.txt")).to.be.true;
+        expect(await dir.exists("/missing.txt")).to.be.false;
+    });
+
+    it("can rename a file", async () => {
+        const dir = new Directory({ "/file.txt": "contents" });
+
+        await dir.rename("/file.txt", "/renamed.txt");
+
+        expect(await dir.exists("/file.txt")).to.be.false;
+        expect(await dir.readTextFile("/renamed.txt")).to.equal("contents");
+    });
+
+    it("can copy a directory", async () => {
+        const dir = new Directory({ "/src/nested/file.txt": "contents" });
+
+        await dir.copy("/src", "/dest");
+
+        expect(await dir.readTextFile("/src/nested/file.txt")).to.equal(
+            "contents",
+        );
+        expect(await dir.readTextFile("/dest/nested/file.txt")).to.equal(
+            "contents",
+        );
+    });
+
+    it("refuses to copy a directory into itself", async () => {
+        const dir = new Directory({ "/a/file.txt": "contents" });
+
+        try {
+            await dir.copy("/a", "/a/./b");
+            expect.fail("The copy should have been rejected");
+        } catch (e: any) {
+            expect(e.message).to.contain("into itself");
+        }
+
+        expect(await dir.exists("/a/b")).to.be.false;
+        expect(await dir.readDir("/a")).to.deep.equal([
+            { name: "file.txt", type: "file" },
+        ]);
+    });
+
+    it("treats copying a file onto itself as a no-op", async () => {
+        const dir = new Directory({ "/file.txt": "contents" });
+
+        await dir.copy("/file.txt", "/./file.txt");
+
+        expect(await dir.readTextFile("/file.txt")).to.equal("contents");
+    });
+
+    it("can create nested directories", async () => {
+        const dir = new Directory();
+
+        await dir.createDir("/a/b/c", { recursive: true });
+        // Creating an existing directory is fine when recursive
+        await dir.createDir("/a/b", { recursive: true });
+
+        expect(await dir.readDir("/a/b")).to.deep.equal([
+            { name: "c", type: "dir" },
+        ]);
+    });
+});
+
+describe("Directory streams", function () {
+    this.timeout("60s").beforeAll(async () => await initialized);
+
+    it("can read a file as a stream", async () => {
+        const dir = new Directory({ "/file.txt": "Hello, World!" });
+
+        const stream = await dir.openReadStream("/file.txt");
+        const text = await new Response(stream).text();
+
+        expect(text).to.equal("Hello, World!");
+    });
+
+    it("can stream a large file", async () => {
+        const contents = new Uint8Array(1024 * 1024).map((_, i) => i % 256);
+        const dir = new Directory();
+        await dir.writeFile("/large.bin", contents);
+
+        const stream = await dir.openReadStream("/large.bin");
+        const buffer = await new Response(stream).arrayBuffer();
+
+        expect(new Uint8Array(buffer)).to.deep.equal(contents);
+    });
+
+    it("can write a file as a stream", async () => {
+        const dir = new Directory({ "/file.txt": "This will be overwritten" });
+
+        const stream = await dir.openWriteStream("/file.txt");
+        const writer = stream.getWriter();
+        await writer.write(encoder.encode("Hello, "));
+        await writer.write(encoder.encode("World!"));
+        await writer.close();
+
+        expect(await dir.readTextFile("/file.txt")).to.equal("Hello, World!");
+    });
+
+    it("can append to a file", async () => {
+        const dir = new Directory({ "/file.txt": "Hello" });
+
+        const stream = await dir.openWriteStream("/file.txt", { append: true });
+        const writer = stream.getWriter();
+        await writer.write(encoder.encode(", World!"));
+        await writer.close();
+
+        expect(await dir.readTextFile("/file.txt")).to.equal("Hello, World!");
+    });
+
+    it("fails to read a file that doesn't exist", async () => {
+        const dir = new Directory();
+
+        try {
+            await dir.openReadStream("/missing.txt");
+            expect.fail("Expected an error");
+        } catch (e) {
+            expect(e).to.be.instanceOf(Error);
+        }
+    });
+});
+
+describe("Overlay Directory", function () {
+    this.timeout("60s").beforeAll(async () => await initialized);
+
+    it("reads through to the lower layer", async () => {
+        const lower = new Directory({ "/nested/file.txt": "Hello, World!" });
+
+        const overlay = Directory.overlay(lower);
+
+        expect(await overlay.readTextFile("/nested/file.txt")).to.equal(
+            "Hello, World!",
+        );
+        expect(await overlay.readDir("/nested")).to.deep.equal([
+            { name: "file.txt", type: "file" },
+        ]);
+    });
+
+    it("sends writes to the upper layer", async () => {
+        const lower = new Directory({ "/file.txt": "original" });
+        const upper = new Directory();
+        const overlay = Directory.overlay(lower, upper);
+
+        await overlay.writeFile("/file.txt", "modified");
+        await overlay.writeFile("/new.txt", "new");
+
+        expect(await overlay.readTextFile("/file.txt")).to.equal("modified");
+        expect(await lower.readTextFile("/file.txt")).to.equal("original");
+        expect(await lower.exists("/new.txt")).to.be.false;
+        expect(await upper.readTextFile("/new.txt")).to.equal("new");
+        expect(await overlay.upper!.readTextFile("/new.txt")).to.equal("new");
+    });
+
+    it("hides deleted files without touching the lower layer", async () => {
+        const lower = new Directory({ "/file.txt": "original" });
+        const overlay = Directory.overlay(lower);
+
+        await overlay.removeFile("/file.txt");
+
+        expect(await overlay.exists("/file.txt")).to.be.false;
+        expect(await lower.readTextFile("/file.txt")).to.equal("original");
+    });
+
+    it("keeps overlays sharing a lower layer isolated", async () => {
+        const lower = new Directory({ "/file.txt": "original" });
+        const first = Directory.overlay(lower);
+        const second = Directory.overlay(lower);
+
+        await first.writeFile("/file.txt", "first");
+
+        expect(await second.readTextFile("/file.txt")).to.equal("original");
+    });
+
+    it("only has an upper layer when it is an overlay", async () => {
+        expect(new Directory().upper).to.be.undefined;
+    });
 });
+
+describe("Directory.watch()", function () {
+    this.timeout("60s").beforeAll(async () => await initialized);
+
+    // Events are delivered asynchronously, so give them a chance to arrive
+    const nextTick = () => new Promise(resolve => setTimeout(resolve, 0));
+
+    it("reports changes made through the Directory", async () => {
+        const dir = new Directory();
+        const events: any[] = [];
+        const watcher = dir.watch("/", e => events.push(e), {
+            recursive: true,
+        });
+
+        await dir.createDir("/nested");
+        await dir.writeFile("/nested/file.txt", "Hello, World!");
+        await dir.rename("/nested/file.txt", "/nested/renamed.txt");
+        await dir.removeFile("/nested/renamed.txt");
+        await nextTick();
+        watcher.close();
+
+        expect(events).to.deep.equal([
+            { type: "create", path: "/nested" },
+            { type: "create", path: "/nested/file.txt" },
+            { type: "modify", path: "/nested/file.txt" },
+            {
+                type: "rename",
+                path: "/nested/renamed.txt",
+                from: "/nested/file.txt",
+            },
+            { type: "delete", path: "/nested/renamed.txt" },
+        ]);
+    });
+
+    it("ignores nested changes unless recursive", async () => {
+        const dir = new Directory({ "/nested/file.txt": "" });
+        const events: any[] = [];
+        const watcher = dir.watch("/", e => events.push(e));
+
+        await dir.writeFile("/top-level.txt", "");
+        await dir.writeFile("/nested/file.txt", "Hello, World!");
+        await nextTick();
+        watcher.close();
+
+        expect(events).to.deep.equal([
+            { type: "create", path: "/top-level.txt" },
+        ]);
+    });
+
+    it("stops reporting changes once closed", async () => {
+        const dir = new Directory();
+        const events: any[] = [];
+        const watcher = dir.watch("/", e => events.push(e));
+
+        watcher.close();
+        await dir.createDir("/nested");
+        await nextTick();
+
+        expect(events).to.be.empty;
+    });
+});
+
+describe("OPFS Directory", function () {
+    this.timeout("60s").beforeAll(async () => await initialized);
+
+    it("persists files across instances", async () => {
+        const path = `wasmer-js-tests/${crypto.randomUUID()}`;
+        const dir = await Directory.opfs(path);
+
+        await dir.createDir("/nested");
+        await dir.writeFile("/nested/file.txt", "Hello, World!");
+        await dir.sync();
+
+        const reopened = await Directory.opfs(path);
+        expect(await reopened.readDir("/")).to.deep.equal([
+            { name: "nested", type: "dir" },
+        ]);
+        expect(await reopened.readTextFile("/nested/file.txt")).to.equal(
+            "Hello, World!",
+        );
+    });
+
+    it("updates files in place", async () => {
+        const path = `wasmer-js-tests/${crypto.randomUUID()}`;
+        const dir = await Directory.opfs(path);
+
+        await dir.createDir("/nested");
+        await dir.writeFile("/nested/file.txt", "A much longer message");
+        await dir.writeFile("/nested/file.txt", "Shorter");
+        await dir.rename("/nested", "/renamed");
+        await dir.sync();
+
+        const reopened = await Directory.opfs(path);
+        expect(await reopened.readDir("/")).to.deep.equal([
+            { name: "renamed", type: "dir" },
+        ]);
+        expect(await reopened.readTextFile("/renamed/file.txt")).to.equal(
+            "Shorter",
+        );
+    });
+
+    it("treats renaming an entry onto itself as a no-op", async () => {
+        const path = `wasmer-js-tests/${crypto.randomUUID()}`;
+        const dir = await Directory.opfs(path);
+        await dir.createDir("/empty");
+        await dir.writeFile("/file.txt", "Hello, World!");
+
+        await dir.rename("/file.txt", "/file.txt");
+        await dir.rename("/empty", "/empty");
+        await dir.sync();
+
+        // The directory should still be usable afterwards
+        await dir.writeFile("/another.txt", "another");
+        await dir.sync();
+        const reopened = await Directory.opfs(path);
+        expect(await reopened.readTextFile("/file.txt")).to.equal(
+            "Hello, World!",
+        );
+        expect(await reopened.readDir("/empty")).to.be.empty;
+        expect(await reopened.readTextFile("/another.txt")).to.equal(
+            "another",
+        );
+    });
+});
+
+describe("Directory archives", function () {
+    this.timeout("60s").beforeAll(async () => await initialized);
+
+    it("can round-trip a tarball", async () => {
+        const dir = new Directory({
+            "/file.txt": "Hello, World!",
+            "/nested/another.txt": "another",
+        });
+        await dir.createDir("/empty");
+
+        const tarball = await dir.toTar();
+        const extracted = await Directory.fromTar(tarball);
+
+        expect(await extracted.readTextFile("/file.txt")).to.equal(
+            "Hello, World!",
+        );
+        expect(await extracted.readTextFile("/nested/another.txt")).to.equal(
+            "another",
+        );
+        expect(await extracted.readDir("/empty")).to.be.empty;
+    });
+
+    it("preserves modes and modification times", async () => {
+        // 2023-11-14T22:13:20Z, which is also representable in a zip archive
+        const mtime = 1_700_000_000;
+        const tarball = tar([
+            { name: "bin/", mode: 0o750, mtime },
+            {
+                name: "bin/script.sh",
+                mode: 0o755,
+                mtime,
+                contents: "#!/bin/sh",
+            },
+        ]);
+
+        const extracted = await Directory.fromTar(tarball);
+        const roundTripped = await Directory.fromTar(await extracted.toTar());
+        const zipped = await Directory.fromZip(await extracted.toZip());
+
+        for (const dir of [extracted, roundTripped, zipped]) {
+            const script = await dir.stat("/bin/script.sh");
+            expect(script.mode).to.equal(0o755);
+            expect(script.modified).to.equal(mtime * 1000);
+            const bin = await dir.stat("/bin");
+            expect(bin.mode).to.equal(0o750);
+            expect(bin.modified).to.equal(mtime * 1000);
+        }
+    });
+
+    it("can export a sub-directory as a gzipped tarball", async () => {
+        const dir = new Directory({
+            "/file.txt": "ignored",
+            "/nested/another.txt": "another",
+        });
+
+        const tarball = await dir.toTar("/nested", { gzip: true });
+        const extracted = await Directory.fromTar(tarball);
+
+        expect(Array.from(tarball.slice(0, 2))).to.deep.equal([0x1f, 0x8b]);
+        expect(await extracted.readDir("/")).to.deep.equal([
+            { name: "another.txt", type: "file" },
+        ]);
+    });
+
+    for (const compression of ["stored", "deflate"] as const) {
+        it(`can round-trip a zip archive (${compression})`, async () => {
+            const dir = new Directory({
+                "/file.txt": "Hello, World!",
+                "/nested/deeply/another.txt": "another",
+                "/ünïcödé/日本語.txt": "こんにちは",
+            });
+            await dir.createDir("/empty");
+
+            const archive = await dir.toZip("/", { compression });
+            const extracted = await Directory.fromZip(archive);
+
+            // Every zip archive starts with a local file header ("PK\x03\x04")
+            expect(Array.from(archive.slice(0, 4))).to.deep.equal([
+                0x50, 0x4b, 0x03, 0x04,
+            ]);
+            expect(await extracted.readTextFile("/file.txt")).to.equal(
+                "Hello, World!",
+            );
+            expect(
+                await extracted.readTextFile("/nested/deeply/another.txt"),
+            ).to.equal("another");
+            expect(
+                await extracted.readTextFile("/ünïcödé/日本語.txt"),
+            ).to.equal("こんにちは");
+            expect(await extracted.readDir("/empty")).to.be.empty;
+        });
+    }
+});
+
+type TarEntry = {
+    name: string;
+    mode: number;
+    mtime: number;
+    contents?: string;
+};
+
+/**
+ * Create a ustar archive by hand, so we have full control over each header.
+ */
+function tar(entries: TarEntry[]): Uint8Array {
+    const blocks: Uint8Array[] = [];
+
+    for (const { name, mode, mtime, contents } of entries) {
+        const data = encoder.encode(contents ?? "");
+        const header = new Uint8Array(512);
+        const field = (offset: number, value: string) =>
+            header.set(encoder.encode(value), offset);
+        const octal = (value: number, width: number) =>
+            value.toString(8).padStart(width - 1, "0") + "\0";
+
+        field(0, name);
+        field(100, octal(mode, 8));
+        field(108, octal(0, 8));
+        field(116, octal(0, 8));
+        field(124, octal(data.length, 12));
+        field(136, octal(mtime, 12));
+        field(148, " ".repeat(8));
+        field(156, contents === undefined ? "5" : "0");
+        field(257, "ustar\0" + "00");
+        const checksum = header.reduce((sum, byte) => sum + byte, 0);
+        field(148, checksum.toString(8).padStart(6, "0") + "\0 ");
+
+        blocks.push(header, data);
+        const padding = (512 - (data.length % 512)) % 512;
+        blocks.push(new Uint8Array(padding));
+    }
+
+    // Archives end with two empty blocks
+    blocks.push(new Uint8Array(1024));
+
+    const archive = new Uint8Array(
+        blocks.reduce((len, block) => len + block.length, 0),
+    );
+    let offset = 0;
+    for (const block of blocks) {
+        archive.set(block, offset);
+        offset += block.length;
+    }
+    return archive;
+}
@@ -1,5 +1,12 @@
 import { expect } from "@esm-bundle/chai";
-import { Wasmer, init, initializeLogger, Directory } from "..";
+import {
+    Wasmer,
+    init,
+    initializeLogger,
+    Directory,
+    Runtime,
+    wat2wasm,
+} from "..";
 
 const encoder = new TextEncoder();
 const decoder = new TextDecoder("utf-8");
@@ -57,6 +64,53 @@ describe("Wasmer.spawn", function () {
         expect(output.stderr).to.equal("Hello\n\nWorld!\n\n");
     });
 
+    it("can use a ReadableStream as stdin", async () => {
+        const pkg = await Wasmer.fromRegistry("sharrattj/coreutils");
+        const stdin = new ReadableStream<Uint8Array>({
+            start(controller) {
+                controller.enqueue(encoder.encode("Hello, "));
+                controller.enqueue(encoder.encode("World!"));
+                controller.close();
+            },
+        });
+
+        const instance = await pkg.commands["cat"].run({ stdin });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.equal("Hello, World!");
+    });
+
+    it("can use an async iterable as stdin", async () => {
+        const pkg = await Wasmer.fromRegistry("sharrattj/coreutils");
+        async function* chunks() {
+            yield encoder.encode("Hello, ");
+            yield encoder.encode("World!");
+        }
+
+        const instance = await pkg.commands["cat"].run({ stdin: chunks() });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.equal("Hello, World!");
+    });
+
+    it("can stream a large Blob into stdin", async () => {
+        const pkg = await Wasmer.fromRegistry("sharrattj/coreutils");
+        // Large enough that the pipe needs to apply backpressure
+        const size = 16 * 1024 * 1024;
+        const stdin = new Blob([new Uint8Array(size)]);
+
+        const instance = await pkg.commands["wc"].run({
+            args: ["-c"],
+            stdin,
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout.trim()).to.equal(size.toString());
+    });
+
     it("Can communicate with a dumb echo program", async () => {
         // First, start our program in the background
         const pkg = await Wasmer.fromRegistry(
@@ -84,6 +138,27 @@ describe("Wasmer.spawn", function () {
         expect(output.code).to.equal(0);
     });
 
+    it("can write to stdin without a terminal", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const instance = await pkg.commands["quickjs"].run({
+            args: [
+                "--std",
+                "--eval",
+                "console.log(os.isatty(0), std.in.getline())",
+            ],
+            tty: false,
+        });
+
+        const stdin = instance.stdin!.getWriter();
+        await stdin.write(encoder.encode("Hello, World!\n"));
+        await stdin.close();
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        // Nothing is echoed back because there is no terminal
+        expect(output.stdout).to.equal("false Hello, World!\n");
+    });
+
     it("Can communicate with a TTY-aware program", async () => {
         // First, start QuickJS up in the background
         const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
@@ -302,6 +377,91 @@ describe("Wasmer.spawn", function () {
         expect(output.ok).to.be.true;
     });
 
+    it("reports changes made by a program to a watched directory", async () => {
+        const dir = new Directory();
+        await dir.writeFile("/file.txt", encoder.encode("Hello, World!"));
+        const deleted = new Promise(resolve => {
+            const watcher = dir.watch("/", event => {
+                watcher.close();
+                resolve(event);
+            });
+        });
+        const pkg = await Wasmer.fromRegistry("sharrattj/coreutils");
+
+        const instance = await pkg.commands["rm"].run({
+            args: ["/mounted/file.txt"],
+            mount: { "/mounted": dir },
+        });
+        await instance.wait();
+
+        expect(await deleted).to.deep.equal({
+            type: "delete",
+            path: "/file.txt",
+        });
+    });
+
+    it("can't delete files from a read-only mount", async () => {
+        const dir = new Directory({ "/file.txt": "Hello, World!" });
+        const pkg = await Wasmer.fromRegistry("sharrattj/coreutils");
+
+        const instance = await pkg.commands["rm"].run({
+            args: ["/mounted/file.txt"],
+            mount: { "/mounted": { directory: dir, readonly: true } },
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.false;
+        expect(await dir.readTextFile("/file.txt")).to.equal("Hello, World!");
+    });
+
+    it("can run a command in a mounted working directory", async () => {
+        const dir = new Directory();
+        const pkg = await Wasmer.fromRegistry("sharrattj/coreutils");
+
+        const instance = await pkg.commands["pwd"].run({
+            mount: { "/mounted": dir },
+            cwd: "/mounted/project",
+            createCwd: true,
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.equal("/mounted/project\n");
+        expect(await dir.exists("/project")).to.be.true;
+    });
+
+    it("rejects a mounted working directory that doesn't exist", async () => {
+        const dir = new Directory();
+        const pkg = await Wasmer.fromRegistry("sharrattj/coreutils");
+
+        try {
+            await pkg.commands["pwd"].run({
+                mount: { "/mounted": dir },
+                cwd: "/mounted/missing",
+            });
+            expect.fail("The missing working directory should be rejected");
+        } catch (e: any) {
+            expect(e.message).to.contain("working directory");
+        }
+    });
+
+    it("can redirect stdout to a file", async () => {
+        const dir = new Directory();
+        const pkg = await Wasmer.fromRegistry("sharrattj/coreutils");
+
+        const instance = await pkg.commands["echo"].run({
+            args: ["Hello, World!"],
+            stdout: { directory: dir, file: "/out/log.txt" },
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.be.empty;
+        expect(await dir.readTextFile("/out/log.txt")).to.equal(
+            "Hello, World!\n",
+        );
+    });
+
     it("can write to a mounted directory", async () => {
         const dir = new Directory();
         const pkg = await Wasmer.fromRegistry("sharrattj/bash");
@@ -316,6 +476,404 @@ describe("Wasmer.spawn", function () {
             "Something else\n",
         );
     });
+
+    it("can kill a running program", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const instance = await pkg.commands["quickjs"].run({
+            args: ["--std", "--eval", "while (true) { os.sleep(10); }"],
+        });
+
+        const waiting = instance.wait();
+        instance.kill("SIGKILL");
+        const output = await waiting;
+
+        expect(output.ok).to.be.false;
+        expect(output.signal).to.equal(9);
+        expect(output.code).to.equal(137);
+    });
+
+    it("kills programs that exceed their timeout", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const instance = await pkg.commands["quickjs"].run({
+            args: [
+                "--std",
+                "--eval",
+                "console.log('Started'); std.out.flush(); while (true) { os.sleep(10); }",
+            ],
+            timeout: 1000,
+        });
+
+        try {
+            await instance.wait();
+            expect.fail("The program should have timed out");
+        } catch (e: any) {
+            expect(e.name).to.equal("TimeoutError");
+            expect(e.stdout).to.equal("Started\n");
+        }
+    });
+
+    it("can limit how much output is collected", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const instance = await pkg.commands["quickjs"].run({
+            args: ["--std", "--eval", "std.out.puts('x'.repeat(10000))"],
+        });
+
+        const output = await instance.wait({ maxOutputBytes: 100 });
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.equal("x".repeat(100));
+        expect(output.stdoutTruncated).to.be.true;
+        expect(output.stderrTruncated).to.be.false;
+    });
+
+    it("can kill programs that exceed the output limit", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const instance = await pkg.commands["quickjs"].run({
+            args: [
+                "--std",
+                "--eval",
+                "while (true) { std.out.puts('y\\n'); std.out.flush(); }",
+            ],
+        });
+
+        const output = await instance.wait({
+            maxOutputBytes: 1024,
+            onLimit: "kill",
+        });
+
+        expect(output.signal).to.equal(9);
+        expect(output.stdoutBytes.length).to.equal(1024);
+        expect(output.stdoutTruncated).to.be.true;
+    });
+});
+
+describe("Package cache", function () {
+    this.timeout("120s").beforeAll(async () => {
+        await initialized;
+    });
+
+    it("can keep packages in Cache Storage", async () => {
+        const name = `wasmer-test-${Math.random()}`;
+        const runtime = new Runtime({
+            packageCache: { backend: "cache-storage", name },
+        });
+
+        try {
+            const pkg = await Wasmer.fromRegistry(
+                "saghul/quickjs@0.0.3",
+                runtime,
+            );
+            expect(pkg.commands["quickjs"]).to.not.be.undefined;
+
+            const cache = await caches.open(name);
+            const keys = await cache.keys();
+            const urls = keys.map(req => req.url);
+            expect(urls.some(url => url.includes("/webc/"))).to.be.true;
+            expect(urls.some(url => url.endsWith("/index.json"))).to.be.true;
+        } finally {
+            await caches.delete(name);
+        }
+    });
+
+    it("re-downloads corrupted packages", async () => {
+        const name = `wasmer-test-${Math.random()}`;
+        const options = {
+            packageCache: { backend: "cache-storage" as const, name },
+        };
+
+        try {
+            await Wasmer.fromRegistry(
+                "saghul/quickjs@0.0.3",
+                new Runtime(options),
+            );
+
+            const cache = await caches.open(name);
+            const keys = await cache.keys();
+            const entry = keys.find(req => req.url.includes("/webc/"))!;
+            await cache.put(entry, new Response("corrupted"));
+
+            const pkg = await Wasmer.fromRegistry(
+                "saghul/quickjs@0.0.3",
+                new Runtime(options),
+            );
+            expect(pkg.commands["quickjs"]).to.not.be.undefined;
+
+            const response = await cache.match(entry);
+            const body = await response!.text();
+            expect(body).to.not.equal("corrupted");
+        } finally {
+            await caches.delete(name);
+        }
+    });
+
+    it("evicts packages once the cache is full", async () => {
+        const name = `wasmer-test-${Math.random()}`;
+        const webcEntries = async () => {
+            const cache = await caches.open(name);
+            const keys = await cache.keys();
+            return keys.filter(req => req.url.includes("/webc/"));
+        };
+        const sizeOf = async (entry: Request) => {
+            const cache = await caches.open(name);
+            const response = await cache.match(entry);
+            return (await response!.arrayBuffer()).byteLength;
+        };
+
+        try {
+            // First, find out how big each package is
+            const unlimited = new Runtime({
+                packageCache: { backend: "cache-storage", name },
+            });
+            await Wasmer.fromRegistry("saghul/quickjs@0.0.3", unlimited);
+            const [quickjs] = await webcEntries();
+            await Wasmer.fromRegistry("sharrattj/coreutils", unlimited);
+            const coreutils = (await webcEntries()).find(
+                req => req.url !== quickjs.url,
+            )!;
+            const sizes = [await sizeOf(quickjs), await sizeOf(coreutils)];
+            await caches.delete(name);
+
+            // Then use a cache which can hold either package, but not both
+            const runtime = new Runtime({
+                packageCache: {
+                    backend: "cache-storage",
+                    name,
+                    maxSize: Math.max(...sizes),
+                },
+            });
+            await Wasmer.fromRegistry("saghul/quickjs@0.0.3", runtime);
+            await Wasmer.fromRegistry("sharrattj/coreutils", runtime);
+
+            const urls = (await webcEntries()).map(req => req.url);
+            expect(urls).to.deep.equal([coreutils.url]);
+        } finally {
+            await caches.delete(name);
+        }
+    });
+});
+
+describe("Wasmer.fromUrl", function () {
+    let url: string;
+    let integrity: string;
+
+    this.timeout("120s").beforeAll(async () => {
+        await initialized;
+
+        // Note: Download a package from the registry then use the copy in
+        // Cache Storage so we can serve it from a URL of our own.
+        const name = `wasmer-test-${Math.random()}`;
+        await Wasmer.fromRegistry(
+            "saghul/quickjs@0.0.3",
+            new Runtime({ packageCache: { backend: "cache-storage", name } }),
+        );
+        const cache = await caches.open(name);
+        const keys = await cache.keys();
+        const entry = keys.find(req => req.url.includes("/webc/"))!;
+        const webc = await (await cache.match(entry))!.arrayBuffer();
+        await caches.delete(name);
+
+        const digest = await crypto.subtle.digest("SHA-256", webc);
+        integrity = `sha256-${btoa(
+            String.fromCharCode(...new Uint8Array(digest)),
+        )}`;
+        url = URL.createObjectURL(new Blob([webc]));
+    });
+
+    this.afterAll(() => URL.revokeObjectURL(url));
+
+    it("can load a package from a URL", async () => {
+        const pkg = await Wasmer.fromUrl(url, { integrity });
+
+        const instance = await pkg.commands["quickjs"].run({
+            args: ["--eval", "console.log('Hello, World!')"],
+        });
+        const output = await instance.wait();
+
+        expect(output.code).to.equal(0);
+        expect(output.stdout).to.equal("Hello, World!\n");
+    });
+
+    it("rejects packages that don't match their integrity", async () => {
+        const wrong = `sha256-${btoa("x".repeat(32))}`;
+
+        try {
+            await Wasmer.fromUrl(url, { integrity: wrong });
+            expect.fail("The package should have been rejected");
+        } catch (e: any) {
+            expect(e.name).to.equal("IntegrityError");
+            expect(e.actual).to.not.equal(e.expected);
+        }
+    });
+});
+
+describe("Wasmer.fromDirectory", function () {
+    this.timeout("120s").beforeAll(async () => {
+        await initialized;
+    });
+
+    it("can build a package from wasmer.toml", async () => {
+        const hello = `(
+            module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory $memory 1)
+                (export "memory" (memory $memory))
+                (data (i32.const 8) "Hello, World!\\n")
+                (func (export "_start")
+                    ;; A single iovec pointing at the message
+                    (i32.store (i32.const 0) (i32.const 8))
+                    (i32.store (i32.const 4) (i32.const 14))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 24))))
+            )`;
+        const dir = new Directory({
+            "wasmer.toml": `
+                [package]
+                name = "wasmer-tests/hello"
+                version = "0.1.0"
+
+                [[module]]
+                name = "hello"
+                source = "hello.wasm"
+
+                [[command]]
+                name = "hello"
+                module = "hello"
+            `,
+            "hello.wasm": wat2wasm(hello),
+        });
+
+        const pkg = await Wasmer.fromDirectory(dir);
+        const instance = await pkg.entrypoint!.run();
+        const output = await instance.wait();
+
+        expect(Object.keys(pkg.commands)).to.deep.equal(["hello"]);
+        expect(output.code).to.equal(0);
+        expect(output.stdout).to.equal("Hello, World!\n");
+    });
+
+    it("rejects commands that refer to unknown modules", async () => {
+        const dir = new Directory({
+            "wasmer.toml": `
+                [package]
+                name = "wasmer-tests/hello"
+                version = "0.1.0"
+
+                [[command]]
+                name = "hello"
+                module = "missing"
+            `,
+        });
+
+        try {
+            await Wasmer.fromDirectory(dir);
+            expect.fail("The package should have been rejected");
+        } catch (e: any) {
+            expect(e.message).to.contain("unknown module");
+        }
+    });
+
+    it("mounts [fs] volumes", async () => {
+        const quickjs = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const dir = new Directory({
+            "wasmer.toml": `
+                [package]
+                name = "wasmer-tests/volumes"
+                version = "0.1.0"
+
+                [[module]]
+                name = "quickjs"
+                source = "quickjs.wasm"
+
+                [[command]]
+                name = "quickjs"
+                module = "quickjs"
+
+                [fs]
+                "/data" = "assets"
+            `,
+            "quickjs.wasm": quickjs.commands["quickjs"].binary(),
+            "assets/nested/message.txt": "Hello, World!",
+        });
+
+        const pkg = await Wasmer.fromDirectory(dir);
+        const instance = await pkg.entrypoint!.run({
+            args: [
+                "--std",
+                "--eval",
+                "console.log(std.open('/data/nested/message.txt', 'r').readAsString())",
+            ],
+        });
+        const output = await instance.wait();
+
+        expect(output.code).to.equal(0);
+        expect(output.stdout).to.equal("Hello, World!\n");
+    });
+
+    it("loads [dependencies] from the registry", async () => {
+        const bash = await Wasmer.fromRegistry("sharrattj/bash");
+        const dir = new Directory({
+            "wasmer.toml": `
+                [package]
+                name = "wasmer-tests/dependencies"
+                version = "0.1.0"
+
+                [dependencies]
+                "sharrattj/coreutils" = "*"
+
+                [[module]]
+                name = "bash"
+                source = "bash.wasm"
+
+                [[command]]
+                name = "bash"
+                module = "bash"
+            `,
+            "bash.wasm": bash.commands["bash"].binary(),
+        });
+
+        const pkg = await Wasmer.fromDirectory(dir);
+        // Note: "cat" comes from the coreutils dependency
+        const instance = await pkg.commands["bash"].run({
+            args: ["-c", "echo 'Hello, World!' | cat"],
+        });
+        const output = await instance.wait();
+
+        expect(output.code).to.equal(0);
+        expect(output.stdout).to.equal("Hello, World!\n");
+    });
+});
+
+describe("Module cache", function () {
+    this.timeout("120s").beforeAll(async () => {
+        await initialized;
+    });
+
+    it("can keep compiled modules in IndexedDB", async () => {
+        const name = `wasmer-test-${Math.random()}`;
+        const runtime = new Runtime({
+            moduleCache: { backend: "indexed-db", name },
+        });
+
+        try {
+            const pkg = await Wasmer.fromRegistry(
+                "saghul/quickjs@0.0.3",
+                runtime,
+            );
+            const instance = await pkg.commands["quickjs"].run({
+                args: ["--eval", "console.log('Hello, World!')"],
+            });
+            const output = await instance.wait();
+            expect(output.code).to.equal(0);
+
+            const keys = await indexedDbKeys(name, "modules");
+            expect(keys).to.have.length(1);
+        } finally {
+            await new Promise(resolve => {
+                const request = indexedDB.deleteDatabase(name);
+                request.onsuccess = request.onerror = resolve;
+            });
+        }
+    });
 });
 
 // FIXME: Re-enable these test and move it to the "Wasmer.spawn" test suite
@@ -593,3 +1151,28 @@ async function* chunks(
         reader.releaseLock();
     }
 }
+
+/**
+ * Get every key in an IndexedDB object store.
+ */
+function indexedDbKeys(
+    database: string,
+    store: string,
+): Promise<IDBValidKey[]> {
+    return new Promise((resolve, reject) => {
+        const open = indexedDB.open(database);
+        open.onerror = () => reject(open.error);
+        open.onsuccess = () => {
+            const db = open.result;
+            const request = db
+                .transaction(store, "readonly")
+                .objectStore(store)
+                .getAllKeys();
+            request.onerror = () => reject(request.error);
+            request.onsuccess = () => {
+                db.close();
+                resolve(request.result);
+            };
+        };
+    });
+}
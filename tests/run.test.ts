@@ -31,6 +31,81 @@ describe("run", function () {
 
         expect(output.ok).to.be.true;
         expect(output.code).to.equal(0);
+        expect(output.reason).to.equal("exit");
+    });
+
+    it("reports traps", async () => {
+        const trap = `(
+            module
+                (memory $memory 0)
+                (export "memory" (memory $memory))
+                (func (export "_start") unreachable)
+            )`;
+        const module = await WebAssembly.compile(wat2wasm(trap));
+
+        const instance = await runWasix(module, { program: "trap" });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.false;
+        expect(output.reason).to.equal("trap");
+        expect(output.trap).to.contain("unreachable");
+        expect(output.error?.causes).to.be.an("array");
+    });
+
+    it("reports programs that can't be started", async () => {
+        const noStart = `(
+            module
+                (memory $memory 0)
+                (export "memory" (memory $memory))
+            )`;
+        const module = await WebAssembly.compile(wat2wasm(noStart));
+
+        const instance = await runWasix(module, { program: "no-start" });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.false;
+        expect(output.reason).to.equal("error");
+        expect(output.error?.message).to.contain("_start");
+    });
+
+    it("reports the exit code of programs that handle SIGTERM", async () => {
+        const handlesSigterm = `(
+            module
+                (import "wasix_32v1" "callback_signal"
+                    (func $callback_signal (param i32 i32)))
+                (import "wasi_snapshot_preview1" "poll_oneoff"
+                    (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit"
+                    (func $proc_exit (param i32)))
+                (memory $memory 1)
+                (export "memory" (memory $memory))
+                (global $signal (mut i32) (i32.const 0))
+                (data (i32.const 0) "__wasm_signal")
+                (func (export "__wasm_signal") (param $sig i32)
+                    (global.set $signal (local.get $sig)))
+                (func (export "_start")
+                    (call $callback_signal (i32.const 0) (i32.const 13))
+                    ;; Subscribe to a 10ms monotonic clock timeout
+                    (i32.store (i32.const 80) (i32.const 1))
+                    (i64.store (i32.const 88) (i64.const 10000000))
+                    (block $done
+                        (loop $wait
+                            (br_if $done (global.get $signal))
+                            (drop (call $poll_oneoff
+                                (i32.const 64) (i32.const 128) (i32.const 1) (i32.const 192)))
+                            (br $wait)))
+                    (call $proc_exit (i32.const 3)))
+            )`;
+        const module = await WebAssembly.compile(wat2wasm(handlesSigterm));
+
+        const instance = await runWasix(module, { program: "handles-sigterm" });
+        const waiting = instance.wait();
+        instance.kill("SIGTERM");
+        const output = await waiting;
+
+        expect(output.code).to.equal(3);
+        expect(output.reason).to.equal("exit");
+        expect(output.signal).to.be.undefined;
     });
 
     it("can start quickjs", async () => {
@@ -147,6 +222,299 @@ describe("run", function () {
         expect(await dir.readTextFile("/file.txt")).to.equal("Hello, World!");
     });
 
+    it("can't write to read-only mounts", async () => {
+        const dir = new Directory({ "/file.txt": "original" });
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+        const script = `
+            const err = {};
+            const f = std.open('/mount/file.txt', 'w', err);
+            console.log(f === null, err.errno === std.Error.EACCES);
+            console.log(std.open('/mount/file.txt', 'r').readAsString());
+        `;
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", script],
+            mount: {
+                "/mount": { directory: dir, readonly: true },
+            },
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.equal("true true\noriginal\n");
+        expect(await dir.readTextFile("/file.txt")).to.equal("original");
+    });
+
+    it("can set the working directory", async () => {
+        const dir = new Directory({ "/project/file.txt": "Hello, World!" });
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+        const script = `
+            console.log(os.getcwd()[0]);
+            console.log(std.open('file.txt', 'r').readAsString());
+        `;
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", script],
+            mount: { "/mount": dir },
+            cwd: "/mount/project",
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.equal("/mount/project\nHello, World!\n");
+    });
+
+    it("can create the working directory", async () => {
+        const dir = new Directory();
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", "console.log(os.getcwd()[0])"],
+            mount: { "/mount": dir },
+            cwd: "/mount/nested/dir",
+            createCwd: true,
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.equal("/mount/nested/dir\n");
+        expect(await dir.readDir("/nested")).to.deep.equal([
+            { name: "dir", type: "dir" },
+        ]);
+    });
+
+    it("rejects a working directory that doesn't exist", async () => {
+        const dir = new Directory();
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+
+        try {
+            await runWasix(quickjs, {
+                program: "quickjs",
+                args: ["--std", "--eval", "console.log(os.getcwd()[0])"],
+                mount: { "/mount": dir },
+                cwd: "/mount/missing",
+            });
+            expect.fail("The missing working directory should be rejected");
+        } catch (e: any) {
+            expect(e.message).to.contain("working directory");
+        }
+
+        expect(await dir.exists("/missing")).to.be.false;
+    });
+
+    it("can redirect output to files", async () => {
+        const dir = new Directory({ "/logs/stderr.txt": "Existing\n" });
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+        const script = `
+            std.out.puts('Hello, stdout!\\n');
+            std.err.puts('Hello, stderr!\\n');
+        `;
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", script],
+            stdout: { directory: dir, file: "/logs/stdout.txt" },
+            stderr: { directory: dir, file: "/logs/stderr.txt", append: true },
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.be.empty;
+        expect(output.stderr).to.be.empty;
+        expect(await dir.readTextFile("/logs/stdout.txt")).to.equal(
+            "Hello, stdout!\n",
+        );
+        expect(await dir.readTextFile("/logs/stderr.txt")).to.equal(
+            "Existing\nHello, stderr!\n",
+        );
+    });
+
+    it("can pass output to callbacks", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+        const script = `
+            std.out.puts('Hello, stdout!\\n');
+            std.err.puts('Hello, stderr!\\n');
+            std.exit(3);
+        `;
+        const decoder = new TextDecoder();
+        let stdout = "";
+        let stderr = "";
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", script],
+            onStdout: chunk => {
+                stdout += decoder.decode(chunk);
+            },
+            onStderr: async chunk => {
+                await new Promise(resolve => setTimeout(resolve, 10));
+                stderr += decoder.decode(chunk);
+            },
+        });
+        const output = await instance.wait();
+
+        expect(output.code).to.equal(3);
+        expect(stdout).to.equal("Hello, stdout!\n");
+        expect(stderr).to.equal("Hello, stderr!\n");
+        // The callbacks consumed everything
+        expect(output.stdout).to.be.empty;
+        expect(output.stderr).to.be.empty;
+    });
+
+    it("only passes output with a callback to callbacks", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+        const script = `
+            std.out.puts('Hello, stdout!\\n');
+            std.err.puts('Hello, stderr!\\n');
+        `;
+        const chunks: Uint8Array[] = [];
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", script],
+            onStdout: chunk => {
+                chunks.push(chunk);
+            },
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(chunks).to.not.be.empty;
+        expect(output.stdout).to.be.empty;
+        expect(output.stderr).to.equal("Hello, stderr!\n");
+    });
+
+    it("can merge stdout and stderr into a single stream", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+        const script = `
+            std.out.puts('first\\n'); std.out.flush();
+            std.err.puts('second\\n'); std.err.flush();
+            std.out.puts('third\\n'); std.out.flush();
+        `;
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", script],
+            mergeStderr: true,
+        });
+        const decoder = new TextDecoder();
+        const chunks: { stream: string; text: string }[] = [];
+        const reader = instance.output!.getReader();
+        while (true) {
+            const { done, value } = await reader.read();
+            if (done) break;
+            chunks.push({
+                stream: value.stream,
+                text: decoder.decode(value.data),
+            });
+        }
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(chunks.map(c => c.text).join("")).to.equal(
+            "first\nsecond\nthird\n",
+        );
+        expect(chunks[0]).to.deep.equal({ stream: "stdout", text: "first\n" });
+        expect(chunks.find(c => c.stream == "stderr")?.text).to.equal(
+            "second\n",
+        );
+    });
+
+    it("splits merged output when waiting for completion", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+        const script = `
+            std.out.puts('out\\n'); std.out.flush();
+            std.err.puts('err\\n'); std.err.flush();
+        `;
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", script],
+            mergeStderr: true,
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.equal("out\n");
+        expect(output.stderr).to.equal("err\n");
+    });
+
+    it("can resize the terminal", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+        const script = `
+            std.in.getline();
+            const [cols, rows] = os.ttyGetWinSize(0);
+            console.log(cols, rows);
+        `;
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", script],
+        });
+        instance.resize(120, 40);
+        const stdin = instance.stdin!.getWriter();
+        await stdin.write(new TextEncoder().encode("\n"));
+        await stdin.close();
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.equal("120 40\n");
+    });
+
+    it("can connect a terminal when stdin is provided", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+        const script = `
+            const line = std.in.getline();
+            console.log(os.isatty(0), "got " + line);
+        `;
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", script],
+            stdin: "hello\n",
+            tty: true,
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        // the terminal echoes our input back
+        expect(output.stdout).to.equal("hello\ntrue got hello\n");
+    });
+
+    it("can disable echo on the terminal", async () => {
+        const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
+        const quickjs = pkg.commands["quickjs"].binary();
+        const script = `
+            const line = std.in.getline();
+            console.log(os.isatty(0), "got " + line);
+        `;
+
+        const instance = await runWasix(quickjs, {
+            program: "quickjs",
+            args: ["--std", "--eval", script],
+            stdin: "hello\n",
+            tty: { echo: false },
+        });
+        const output = await instance.wait();
+
+        expect(output.ok).to.be.true;
+        expect(output.stdout).to.equal("true got hello\n");
+    });
+
     it("can accept strings as stdin", async () => {
         const pkg = await Wasmer.fromRegistry("saghul/quickjs@0.0.3");
         const quickjs = pkg.commands["quickjs"].binary();